@@ -0,0 +1,10 @@
+mod lz4;
+mod lzma;
+mod mangled_zlib;
+mod registry;
+mod zlib;
+pub use lz4::*;
+pub use lzma::*;
+pub use mangled_zlib::*;
+pub use registry::*;
+pub use zlib::*;
@@ -0,0 +1,89 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{announced_size, check_limit, Codec, CodecError};
+
+/// Most a lz4 block expands, a length byte never stands for more than 255 decoded bytes
+const MAX_EXPANSION: usize = 255;
+
+/// Decompresses a raw lz4 `block` that decodes to `size` bytes.
+///
+/// Sizes above `limit` or beyond what the block could expand to fail before
+/// anything is allocated.
+pub fn decompress_block(block: &[u8], size: usize, limit: usize) -> Result<Vec<u8>, CodecError> {
+    check_limit(size, limit)?;
+    if size > block.len().saturating_mul(MAX_EXPANSION) {
+        return Err(CodecError::InvalidSize(size as i64));
+    }
+    Ok(lz4_flex::decompress(block, size)?)
+}
+
+/// `ZZZ4` followed by a lz4 block with the uncompressed size prepended.
+///
+/// Used for whole MPK entries as well as for single texture mips.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Lz4;
+
+impl Codec for Lz4 {
+    fn name(&self) -> &'static str {
+        "ZZZ4"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        buffer.starts_with(b"ZZZ4")
+    }
+
+    fn decoded_size(&self, buffer: &[u8]) -> Option<usize> {
+        buffer
+            .get(4..8)
+            .map(|size| LittleEndian::read_u32(size) as usize)
+    }
+
+    fn decode_limited(&self, buffer: &[u8], limit: usize) -> Result<Vec<u8>, CodecError> {
+        let size = self.decoded_size(buffer).ok_or(CodecError::Truncated())?;
+        decompress_block(&buffer[8..], size, limit)
+    }
+}
+
+/// `CCCC` + `ZZZ4`, the uncompressed size and a lz4 block followed by a 20 byte trailer.
+///
+/// The trailer has the size of a SHA-1 digest, but it matches no digest of the block or
+/// the decoded data, so what it holds is unknown and it is skipped. [`Codec::verify`]
+/// only checks that the block decodes to the announced size and reports
+/// [`crate::Integrity::Unchecked`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CompressedLz4;
+
+impl CompressedLz4 {
+    pub const TRAILER_SIZE: usize = 20;
+
+    /// Splits the payload into compressed block and trailer
+    fn split(buffer: &[u8]) -> Result<(&[u8], &[u8]), CodecError> {
+        let buffer = buffer.get(12..).ok_or(CodecError::Truncated())?;
+        let end = buffer
+            .len()
+            .checked_sub(Self::TRAILER_SIZE)
+            .ok_or(CodecError::Truncated())?;
+        Ok(buffer.split_at(end))
+    }
+}
+
+impl Codec for CompressedLz4 {
+    fn name(&self) -> &'static str {
+        "CCCC/ZZZ4"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        buffer.len() >= 12 && buffer.starts_with(b"CCCCZZZ4")
+    }
+
+    fn decoded_size(&self, buffer: &[u8]) -> Option<usize> {
+        announced_size(buffer).ok()
+    }
+
+    fn decode_limited(&self, buffer: &[u8], limit: usize) -> Result<Vec<u8>, CodecError> {
+        let uncompressed_size = announced_size(buffer)?;
+        check_limit(uncompressed_size, limit)?;
+        let (block, _trailer) = Self::split(buffer)?;
+        decompress_block(block, uncompressed_size, limit)
+    }
+}
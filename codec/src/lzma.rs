@@ -0,0 +1,46 @@
+use crate::{announced_size, check_limit, Codec, CodecError};
+
+/// `CCCC` + `LZMA`, the uncompressed size followed by a plain lzma stream.
+///
+/// Neither the container nor the stream carry a checksum. [`Codec::verify`] decodes the
+/// whole stream and checks it ends in exactly the announced size, which catches truncated
+/// payloads, and reports [`crate::Integrity::Unchecked`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CompressedLzma;
+
+impl Codec for CompressedLzma {
+    fn name(&self) -> &'static str {
+        "CCCC/LZMA"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        buffer.len() >= 12 && buffer.starts_with(b"CCCCLZMA")
+    }
+
+    fn decoded_size(&self, buffer: &[u8]) -> Option<usize> {
+        announced_size(buffer).ok()
+    }
+
+    /// The dictionary the stream asks for counts against `limit` as well
+    fn decode_limited(&self, buffer: &[u8], limit: usize) -> Result<Vec<u8>, CodecError> {
+        let uncompressed_size = announced_size(buffer)?;
+        check_limit(uncompressed_size, limit)?;
+        let mut decompressed = vec![];
+        lzma_rs::lzma_decompress_with_options(
+            &mut std::io::Cursor::new(&buffer[12..]),
+            &mut decompressed,
+            &lzma_rs::decompress::Options {
+                unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(
+                    uncompressed_size as u64,
+                )),
+                memlimit: Some(limit),
+                allow_incomplete: false,
+            },
+        )?;
+        Ok(decompressed)
+    }
+
+    fn sniff_name(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+
+use crate::{Codec, CodecError, Integrity, Zlib};
+
+/// Key the head of the stream is xor'ed with
+const KEY: u8 = 154;
+
+/// Size of the tail behind the zlib stream
+const TAIL_SIZE: usize = 8;
+
+/// A zlib stream with its head xor'ed with 154 and an 8 byte tail, mostly used for scripts.
+///
+/// The mangled range depends on the payload size: the first `128 - (len - 8) % 37` bytes.
+/// The stream uses the default compression level, its `78 9C` header turns into the
+/// `E2 06` the container is recognised by.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MangledZlib;
+
+impl MangledZlib {
+    /// Deflates `data` into a mangled stream with an all zero tail
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, CodecError> {
+        self.encode_with_tail(data, [0; TAIL_SIZE])
+    }
+
+    /// Deflates `data` and mangles the stream the way [`Codec::decode`] expects it.
+    ///
+    /// What the game stores in the tail is not known, decoding ignores it. Pass the
+    /// [`MangledZlib::tail`] of the payload being replaced to keep it as it was.
+    pub fn encode_with_tail(
+        &self,
+        data: &[u8],
+        tail: [u8; TAIL_SIZE],
+    ) -> Result<Vec<u8>, CodecError> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let mut buffer = encoder.finish()?;
+        buffer.extend_from_slice(&tail);
+        toggle_head(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// The tail of a mangled payload
+    pub fn tail(&self, buffer: &[u8]) -> Result<[u8; TAIL_SIZE], CodecError> {
+        let mut tail = [0; TAIL_SIZE];
+        let start = buffer
+            .len()
+            .checked_sub(TAIL_SIZE)
+            .ok_or(CodecError::Truncated())?;
+        tail.copy_from_slice(&buffer[start..]);
+        // Short payloads are mangled as a whole, tail included
+        let mangled = mangled_len(buffer.len())?;
+        for x in tail.iter_mut().take(mangled.saturating_sub(start)) {
+            *x ^= KEY;
+        }
+        Ok(tail)
+    }
+
+    /// Size of the mangled payload at the start of `buffer`, which may run on into
+    /// unrelated data. `None` if no stream decoding to at most `limit` bytes starts there.
+    ///
+    /// The mangled head depends on the payload size, so each of the 37 possible heads is
+    /// undone and the stream inflated from the borrowed buffer up to its end. The size
+    /// found that way has to give the same head.
+    pub fn stream_len(&self, buffer: &[u8], limit: usize) -> Option<usize> {
+        if !self.detect(buffer) {
+            return None;
+        }
+        let (head, rest) = buffer.split_at(buffer.len().min(128));
+        (0..37).find_map(|offset| {
+            let mut unmangled = head.to_vec();
+            for x in unmangled.iter_mut().take(128 - offset) {
+                *x ^= KEY;
+            }
+            let len = inflated_len([&unmangled, rest], limit)? + TAIL_SIZE;
+            (len <= buffer.len() && (len - TAIL_SIZE) % 37 == offset).then_some(len)
+        })
+    }
+}
+
+/// Bytes of `segments` the zlib stream at their start takes up, `None` if it is invalid,
+/// truncated or decodes to more than `limit` bytes. The decoded data is thrown away.
+fn inflated_len(segments: [&[u8]; 2], limit: usize) -> Option<usize> {
+    let mut inflater = Decompress::new(true);
+    let mut output = vec![0; 1 << 16];
+    for segment in segments {
+        let start = inflater.total_in();
+        loop {
+            let before = (inflater.total_in(), inflater.total_out());
+            let consumed = (before.0 - start) as usize;
+            let status = inflater
+                .decompress(&segment[consumed..], &mut output, FlushDecompress::None)
+                .ok()?;
+            if status == Status::StreamEnd {
+                return usize::try_from(inflater.total_in()).ok();
+            }
+            if inflater.total_out() > limit as u64 {
+                return None;
+            }
+            if (inflater.total_in(), inflater.total_out()) == before {
+                break;
+            }
+        }
+    }
+    None
+}
+
+/// Number of leading bytes xor'ed in a payload of `len` bytes
+fn mangled_len(len: usize) -> Result<usize, CodecError> {
+    let offset = len.checked_sub(TAIL_SIZE).ok_or(CodecError::Truncated())? % 37;
+    Ok((128 - offset).min(len))
+}
+
+/// Mangles or unmangles the head of a payload, xor'ing twice is a no-op
+fn toggle_head(buffer: &mut [u8]) -> Result<usize, CodecError> {
+    let end = mangled_len(buffer.len())?;
+    for x in buffer[..end].iter_mut() {
+        *x ^= KEY;
+    }
+    Ok(end)
+}
+
+impl Codec for MangledZlib {
+    fn name(&self) -> &'static str {
+        "E2 06 zlib"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        buffer.starts_with(b"\xE2\x06")
+    }
+
+    fn decoded_size(&self, _buffer: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn decode_limited(&self, buffer: &[u8], limit: usize) -> Result<Vec<u8>, CodecError> {
+        // TODO(alexander): Reduce number of vec allocations
+        let mut buffer = buffer.to_vec();
+
+        let end = toggle_head(&mut buffer)?;
+        let end = if end == buffer.len() {
+            end
+        } else {
+            buffer.len() - TAIL_SIZE
+        };
+        Zlib.decode_limited(&buffer[..end], limit)
+    }
+
+    /// zlib streams end in an adler32 of the decoded data, which decoding already checks
+    fn verify_limited(&self, buffer: &[u8], limit: usize) -> Result<Integrity, CodecError> {
+        self.decode_limited(buffer, limit)?;
+        Ok(Integrity::Verified)
+    }
+
+    fn sniff_name(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,215 @@
+use byteorder::{ByteOrder, LittleEndian};
+use thiserror::Error;
+
+use crate::{CompressedLz4, CompressedLzma, Lz4, MangledZlib};
+
+#[derive(Error, Debug)]
+pub enum CodecError {
+    #[error("payload is too short for its container")]
+    Truncated(),
+    #[error("lz4 decompression failed")]
+    Lz4(#[from] lz4_flex::block::DecompressError),
+    #[error("lzma decompression failed")]
+    Lzma(#[from] lzma_rs::error::Error),
+    #[error("error reading")]
+    ReadError(#[from] std::io::Error),
+    #[error("decoded {actual} bytes but the header announced {expected}")]
+    SizeMismatch { expected: usize, actual: usize },
+    #[error("header announces a decoded size of {0} bytes")]
+    InvalidSize(i64),
+    #[error("decoded data exceeds the limit of {limit} bytes")]
+    TooLarge { limit: usize },
+    #[error("container {0:?} is not known")]
+    UnknownContainer(String),
+}
+
+/// Outcome of [`Codec::verify`] for a payload that could be decoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Integrity {
+    /// The container carries a checksum and it matched
+    Verified,
+    /// Decoding succeeded but the container has no checksum to compare against
+    Unchecked,
+}
+
+/// A container NetEase wraps payloads in, identified by its header
+pub trait Codec: Send + Sync {
+    /// Short name of the container, e.g. `CCCC/ZZZ4`
+    fn name(&self) -> &'static str;
+
+    /// Whether `buffer` starts with this container's header
+    fn detect(&self, buffer: &[u8]) -> bool;
+
+    /// Decoded size as announced by the header, `None` if the container doesn't store it
+    fn decoded_size(&self, buffer: &[u8]) -> Option<usize>;
+
+    fn decode(&self, buffer: &[u8]) -> Result<Vec<u8>, CodecError> {
+        self.decode_limited(buffer, usize::MAX)
+    }
+
+    /// Decodes `buffer` without ever holding more than `limit` decoded bytes, sizes
+    /// announced by the header are checked before anything is allocated
+    fn decode_limited(&self, buffer: &[u8], limit: usize) -> Result<Vec<u8>, CodecError>;
+
+    fn verify(&self, buffer: &[u8]) -> Result<Integrity, CodecError> {
+        self.verify_limited(buffer, usize::MAX)
+    }
+
+    /// Decodes `buffer` and checks whatever the container allows checking.
+    ///
+    /// Truncated or damaged payloads fail with an error, the default only compares
+    /// the decoded size against the header.
+    fn verify_limited(&self, buffer: &[u8], limit: usize) -> Result<Integrity, CodecError> {
+        let decoded = self.decode_limited(buffer, limit)?;
+        check_decoded_size(self.decoded_size(buffer), &decoded)?;
+        Ok(Integrity::Unchecked)
+    }
+
+    /// Whether the original name is lost for payloads in this container,
+    /// so the decoded content should be inspected to find a better one
+    fn sniff_name(&self) -> bool {
+        false
+    }
+}
+
+/// Fails if a header announces more than `limit` decoded bytes
+pub(crate) fn check_limit(size: usize, limit: usize) -> Result<(), CodecError> {
+    if size > limit {
+        return Err(CodecError::TooLarge { limit });
+    }
+    Ok(())
+}
+
+/// Decoded size the `CCCC` containers store behind their 8 byte magic
+pub(crate) fn announced_size(buffer: &[u8]) -> Result<usize, CodecError> {
+    let size = buffer
+        .get(8..12)
+        .map(LittleEndian::read_i32)
+        .ok_or(CodecError::Truncated())?;
+    usize::try_from(size).map_err(|_| CodecError::InvalidSize(size.into()))
+}
+
+pub(crate) fn check_decoded_size(
+    expected: Option<usize>,
+    decoded: &[u8],
+) -> Result<(), CodecError> {
+    match expected {
+        Some(expected) if expected != decoded.len() => Err(CodecError::SizeMismatch {
+            expected,
+            actual: decoded.len(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Decoded size [`CodecRegistry`] allows per payload unless told otherwise, well above
+/// any real asset but small enough that a corrupt header can't exhaust memory
+pub const DEFAULT_MAX_DECODED_SIZE: usize = 512 * 1024 * 1024;
+
+/// Set of known codecs, the first one detecting a payload decodes it
+pub struct CodecRegistry {
+    codecs: Vec<Box<dyn Codec>>,
+    max_decoded_size: usize,
+}
+
+impl CodecRegistry {
+    /// A registry without any codecs, everything is treated as raw data
+    pub fn empty() -> Self {
+        Self {
+            codecs: Vec::new(),
+            max_decoded_size: DEFAULT_MAX_DECODED_SIZE,
+        }
+    }
+
+    /// Most bytes a single payload may decode to, larger ones fail with
+    /// [`CodecError::TooLarge`] instead of being allocated
+    pub fn set_max_decoded_size(&mut self, size: usize) {
+        self.max_decoded_size = size;
+    }
+
+    pub fn max_decoded_size(&self) -> usize {
+        self.max_decoded_size
+    }
+
+    /// Adds a codec, it takes precedence over every codec registered before it
+    pub fn register(&mut self, codec: Box<dyn Codec>) {
+        self.codecs.insert(0, codec);
+    }
+
+    pub fn detect(&self, buffer: &[u8]) -> Option<&dyn Codec> {
+        self.codecs
+            .iter()
+            .find(|codec| codec.detect(buffer))
+            .map(|codec| codec.as_ref())
+    }
+
+    /// Name of the detected container, `raw` if no codec handles the payload
+    pub fn container_name(&self, buffer: &[u8]) -> &'static str {
+        self.detect(buffer)
+            .map(|codec| codec.name())
+            .unwrap_or("raw")
+    }
+
+    /// Decodes `buffer` with the first codec detecting it, raw payloads are returned as is
+    pub fn decode<'a>(
+        &'a self,
+        buffer: &[u8],
+    ) -> Result<(Option<&'a dyn Codec>, Vec<u8>), CodecError> {
+        match self.detect(buffer) {
+            Some(codec) => Ok((
+                Some(codec),
+                codec.decode_limited(buffer, self.max_decoded_size)?,
+            )),
+            None => Ok((None, buffer.to_vec())),
+        }
+    }
+}
+
+impl Default for CodecRegistry {
+    /// All containers found in Messiah archives so far
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(UnknownCompressed));
+        registry.register(Box::new(MangledZlib));
+        registry.register(Box::new(CompressedLzma));
+        registry.register(Box::new(CompressedLz4));
+        registry.register(Box::new(Lz4));
+        registry
+    }
+}
+
+impl std::fmt::Debug for CodecRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.codecs.iter().map(|codec| codec.name()))
+            .finish()
+    }
+}
+
+/// `CCCC` followed by a codec we don't know yet.
+///
+/// Detected so these payloads aren't mistaken for raw data, decoding them fails with
+/// [`CodecError::UnknownContainer`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct UnknownCompressed;
+
+impl Codec for UnknownCompressed {
+    fn name(&self) -> &'static str {
+        "CCCC"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        buffer.starts_with(b"CCCC")
+    }
+
+    fn decoded_size(&self, _buffer: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn decode_limited(&self, buffer: &[u8], _limit: usize) -> Result<Vec<u8>, CodecError> {
+        let magic = buffer.get(..8).unwrap_or(buffer);
+        Err(CodecError::UnknownContainer(
+            String::from_utf8_lossy(magic).into_owned(),
+        ))
+    }
+}
@@ -0,0 +1,60 @@
+use flate2::{Decompress, FlushDecompress, Status};
+
+use crate::{Codec, CodecError, Integrity};
+
+/// A plain zlib stream.
+///
+/// Not part of [`crate::CodecRegistry::default`], the two byte header is too weak to
+/// detect it in arbitrary payloads. Archives flagging zlib entries decode them directly.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Zlib;
+
+impl Codec for Zlib {
+    fn name(&self) -> &'static str {
+        "zlib"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        match buffer {
+            [cmf, flg, ..] => cmf & 0x0F == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+            _ => false,
+        }
+    }
+
+    fn decoded_size(&self, _buffer: &[u8]) -> Option<usize> {
+        None
+    }
+
+    /// The stream doesn't announce its size, decoding stops once it passes `limit`.
+    /// Streams missing their end, including the adler32 behind it, are truncated.
+    fn decode_limited(&self, buffer: &[u8], limit: usize) -> Result<Vec<u8>, CodecError> {
+        let mut inflater = Decompress::new(true);
+        let mut result_buffer = Vec::new();
+        loop {
+            let before = (inflater.total_in(), inflater.total_out());
+            result_buffer.reserve(1 << 16);
+            let status = inflater
+                .decompress_vec(
+                    &buffer[before.0 as usize..],
+                    &mut result_buffer,
+                    FlushDecompress::None,
+                )
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+            if result_buffer.len() > limit {
+                return Err(CodecError::TooLarge { limit });
+            }
+            if status == Status::StreamEnd {
+                return Ok(result_buffer);
+            }
+            if (inflater.total_in(), inflater.total_out()) == before {
+                return Err(CodecError::Truncated());
+            }
+        }
+    }
+
+    /// The stream ends in an adler32 of the decoded data, which decoding already checks
+    fn verify_limited(&self, buffer: &[u8], limit: usize) -> Result<Integrity, CodecError> {
+        self.decode_limited(buffer, limit)?;
+        Ok(Integrity::Verified)
+    }
+}
@@ -0,0 +1,32 @@
+use messiah_codec::{Codec, CodecError, Lz4};
+use proptest::prelude::*;
+
+fn zzz4(size: u32, block: &[u8]) -> Vec<u8> {
+    [b"ZZZ4", &size.to_le_bytes()[..], block].concat()
+}
+
+proptest! {
+    #[test]
+    fn round_trip_compressible(byte in any::<u8>(), len in 0usize..1 << 20) {
+        let data = vec![byte; len];
+        let block = lz4_flex::compress(&data);
+        prop_assert_eq!(Lz4.decode(&zzz4(len as u32, &block)).unwrap(), data);
+    }
+}
+
+#[test]
+fn implausible_size_is_rejected_before_allocating() {
+    let block = lz4_flex::compress(b"tiny");
+    let error = Lz4
+        .decode_limited(&zzz4(u32::MAX, &block), usize::MAX)
+        .unwrap_err();
+    assert!(matches!(error, CodecError::InvalidSize(_)), "{:?}", error);
+}
+
+#[test]
+fn limit_is_checked_first() {
+    let data = vec![0; 4096];
+    let block = lz4_flex::compress(&data);
+    let error = Lz4.decode_limited(&zzz4(4096, &block), 1024).unwrap_err();
+    assert!(matches!(error, CodecError::TooLarge { limit: 1024 }));
+}
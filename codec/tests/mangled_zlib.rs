@@ -0,0 +1,55 @@
+use messiah_codec::{Codec, CodecRegistry, Integrity, MangledZlib};
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn round_trip(data in prop::collection::vec(any::<u8>(), 0..4096), tail in any::<[u8; 8]>()) {
+        let encoded = MangledZlib.encode_with_tail(&data, tail).unwrap();
+        prop_assert!(MangledZlib.detect(&encoded));
+        prop_assert_eq!(MangledZlib.tail(&encoded).unwrap(), tail);
+        prop_assert_eq!(MangledZlib.decode(&encoded).unwrap(), data);
+        prop_assert_eq!(MangledZlib.verify(&encoded).unwrap(), Integrity::Verified);
+    }
+
+    #[test]
+    fn round_trip_compressible(byte in any::<u8>(), len in 0usize..1 << 16) {
+        let data = vec![byte; len];
+        let encoded = MangledZlib.encode(&data).unwrap();
+        prop_assert_eq!(MangledZlib.tail(&encoded).unwrap(), [0; 8]);
+        prop_assert_eq!(MangledZlib.decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn registry_picks_mangled_zlib(data in prop::collection::vec(any::<u8>(), 0..1024)) {
+        let encoded = MangledZlib.encode(&data).unwrap();
+        let registry = CodecRegistry::default();
+        let codec = registry.detect(&encoded).unwrap();
+        prop_assert_eq!(codec.name(), MangledZlib.name());
+        prop_assert_eq!(codec.decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn reencoding_keeps_tail(data in prop::collection::vec(any::<u8>(), 0..1024), tail in any::<[u8; 8]>()) {
+        let original = MangledZlib.encode_with_tail(&data, tail).unwrap();
+        let decoded = MangledZlib.decode(&original).unwrap();
+        let reencoded = MangledZlib
+            .encode_with_tail(&decoded, MangledZlib.tail(&original).unwrap())
+            .unwrap();
+        prop_assert_eq!(reencoded, original);
+    }
+
+    #[test]
+    fn stream_len_ignores_trailing_data(data in prop::collection::vec(any::<u8>(), 0..4096), tail in any::<[u8; 8]>(), rest in prop::collection::vec(any::<u8>(), 0..256)) {
+        let encoded = MangledZlib.encode_with_tail(&data, tail).unwrap();
+        let mut buffer = encoded.clone();
+        buffer.extend_from_slice(&rest);
+        prop_assert_eq!(MangledZlib.stream_len(&buffer, usize::MAX), Some(encoded.len()));
+        prop_assert_eq!(MangledZlib.stream_len(&encoded[..encoded.len() - 1], usize::MAX), None);
+    }
+}
+
+#[test]
+fn too_short() {
+    assert!(MangledZlib.decode(b"\xE2\x06").is_err());
+    assert!(MangledZlib.tail(b"\xE2\x06").is_err());
+}
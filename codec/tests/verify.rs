@@ -0,0 +1,91 @@
+use messiah_codec::{Codec, CompressedLz4, CompressedLzma, Integrity, Lz4, MangledZlib, Zlib};
+
+fn data() -> Vec<u8> {
+    (0..4096u32).map(|index| (index * 7 % 251) as u8).collect()
+}
+
+/// Flips a byte in the middle of the payload, behind any header
+fn corrupt(buffer: &[u8]) -> Vec<u8> {
+    let mut buffer = buffer.to_vec();
+    let middle = buffer.len() / 2;
+    buffer[middle] ^= 0x55;
+    buffer
+}
+
+#[test]
+fn zlib() {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, &data()).unwrap();
+    let encoded = encoder.finish().unwrap();
+    assert_eq!(Zlib.verify(&encoded).unwrap(), Integrity::Verified);
+    assert!(Zlib.verify(&corrupt(&encoded)).is_err());
+    assert!(Zlib.verify(&encoded[..encoded.len() - 4]).is_err());
+}
+
+#[test]
+fn mangled_zlib() {
+    let encoded = MangledZlib.encode(&data()).unwrap();
+    assert_eq!(MangledZlib.verify(&encoded).unwrap(), Integrity::Verified);
+    assert!(MangledZlib.verify(&corrupt(&encoded)).is_err());
+    assert!(MangledZlib.verify(&encoded[..encoded.len() / 2]).is_err());
+}
+
+#[test]
+fn lz4() {
+    let data = data();
+    let mut encoded = b"ZZZ4".to_vec();
+    encoded.extend(lz4_flex::compress_prepend_size(&data));
+    assert_eq!(Lz4.verify(&encoded).unwrap(), Integrity::Unchecked);
+    assert!(Lz4.verify(&encoded[..encoded.len() / 2]).is_err());
+
+    // A larger announced size than the block holds
+    let mut grown = encoded.clone();
+    grown[4..8].copy_from_slice(&(data.len() as u32 + 1).to_le_bytes());
+    assert!(Lz4.verify(&grown).is_err());
+}
+
+#[test]
+fn compressed_lz4_is_unchecked() {
+    let data = data();
+    let mut encoded = b"CCCCZZZ4".to_vec();
+    encoded.extend((data.len() as i32).to_le_bytes());
+    encoded.extend(lz4_flex::compress(&data));
+    encoded.extend([0; CompressedLz4::TRAILER_SIZE]);
+    assert_eq!(
+        CompressedLz4.verify(&encoded).unwrap(),
+        Integrity::Unchecked
+    );
+    assert_eq!(CompressedLz4.decode(&encoded).unwrap(), data);
+
+    // The trailer isn't checked, but the block is
+    let end = encoded.len() - CompressedLz4::TRAILER_SIZE;
+    let mut trailer = encoded.clone();
+    trailer[end] ^= 0xff;
+    assert_eq!(
+        CompressedLz4.verify(&trailer).unwrap(),
+        Integrity::Unchecked
+    );
+    let mut cut = encoded[..end - 10].to_vec();
+    cut.extend([0; CompressedLz4::TRAILER_SIZE]);
+    assert!(CompressedLz4.verify(&cut).is_err());
+}
+
+#[test]
+fn compressed_lzma_is_unchecked() {
+    let data = data();
+    let mut stream = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut stream).unwrap();
+    // The container keeps the properties but drops the size of the lzma header
+    let mut encoded = b"CCCCLZMA".to_vec();
+    encoded.extend((data.len() as i32).to_le_bytes());
+    encoded.extend(&stream[..5]);
+    encoded.extend(&stream[13..]);
+    assert_eq!(
+        CompressedLzma.verify(&encoded).unwrap(),
+        Integrity::Unchecked
+    );
+    assert_eq!(CompressedLzma.decode(&encoded).unwrap(), data);
+    assert!(CompressedLzma
+        .verify(&encoded[..encoded.len() / 2])
+        .is_err());
+}
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use messiah_codec::{Codec, CompressedLz4};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = CompressedLz4.decode_limited(data, 1 << 24);
+    let _ = CompressedLz4.verify_limited(data, 1 << 24);
+});
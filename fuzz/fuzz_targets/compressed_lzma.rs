@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use messiah_codec::{Codec, CompressedLzma};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = CompressedLzma.decode_limited(data, 1 << 24);
+    let _ = CompressedLzma.verify_limited(data, 1 << 24);
+});
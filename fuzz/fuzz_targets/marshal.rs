@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use messiah_mpk::{PyObject, PythonVersion};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the layout of code objects
+    let Some((version, data)) = data.split_first() else {
+        return;
+    };
+    let version = match version % 4 {
+        0 => PythonVersion::Version2_7,
+        1 => PythonVersion::Version3_7,
+        2 => PythonVersion::Version3_11,
+        _ => PythonVersion::Version3_13,
+    };
+    let _ = PyObject::from_marshal(data, version);
+});
@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use messiah_mpk::MPKFileReader;
+
+/// Most decoded bytes per entry, keeps hostile sizes from tripping the rss limit
+const MAX_DECODED_SIZE: usize = 1 << 24;
+
+fuzz_target!(|data: &[u8]| {
+    // The first two bytes split the input into the index and a single shard
+    let Some((split, data)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let (index, shard) = data.split_at((u16::from_le_bytes(*split) as usize).min(data.len()));
+
+    let dir = std::env::temp_dir().join(format!("messiah-fuzz-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let info_path = dir.join("fuzz.mpkinfo");
+    std::fs::write(&info_path, index).unwrap();
+    std::fs::write(dir.join("fuzz.mpk"), shard).unwrap();
+
+    let Ok(mut reader) = MPKFileReader::new(&info_path) else {
+        return;
+    };
+    reader.codecs_mut().set_max_decoded_size(MAX_DECODED_SIZE);
+    for index in 0..reader.entry_count().min(64) {
+        let _ = reader.open_entry(index);
+    }
+    let _ = reader.list_entries();
+    let _ = reader.verify_entries();
+    let _ = reader.check_integrity();
+});
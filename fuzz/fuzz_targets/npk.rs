@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use messiah_mpk::NPKFileReader;
+
+/// Most decoded bytes per entry, keeps hostile sizes from tripping the rss limit
+const MAX_DECODED_SIZE: usize = 1 << 24;
+
+fuzz_target!(|data: &[u8]| {
+    let dir = std::env::temp_dir().join(format!("messiah-fuzz-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fuzz.npk");
+    std::fs::write(&path, data).unwrap();
+
+    let Ok(mut reader) = NPKFileReader::new(&path) else {
+        return;
+    };
+    reader.codecs_mut().set_max_decoded_size(MAX_DECODED_SIZE);
+    for index in 0..reader.entries().len().min(64) {
+        let _ = reader.open_entry(index);
+    }
+    let _ = reader.list_entries();
+});
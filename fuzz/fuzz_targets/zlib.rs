@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use messiah_codec::{Codec, Zlib};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Zlib.decode_limited(data, 1 << 24);
+    let _ = Zlib.verify_limited(data, 1 << 24);
+});
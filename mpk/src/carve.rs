@@ -0,0 +1,355 @@
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use memmap2::Mmap;
+use messiah_codec::{CodecRegistry, MangledZlib};
+use serde::Serialize;
+
+use crate::signature::TEXTURE2D_MAGIC;
+use crate::{
+    helpers, shard_path, MPKFileEntry, MPKFileEntryV1, MPKFileHeader, MPKFileReader,
+    SignatureRegistry,
+};
+
+/// Candidate ends tried for a container before its boundary is guessed
+const DEFAULT_LOOKAHEAD: usize = 16;
+
+/// Largest expansion we believe a container announcing its decoded size, anything
+/// above is a signature showing up inside unrelated data
+const MAX_EXPANSION: usize = 256;
+
+/// Start of an entry recognised while carving
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CarveSignature {
+    /// `ZZZ4`
+    Lz4,
+    /// `CCCCZZZ4`
+    CompressedLz4,
+    /// `CCCCLZMA`
+    CompressedLzma,
+    /// `CCCC` followed by a codec we don't know
+    Compressed,
+    /// `LZMA` without the `CCCC` in front
+    Lzma,
+    /// `\xE2\x06` mangled zlib
+    MangledZlib,
+    /// Uncompressed Texture2D
+    Texture,
+    /// Uncompressed pyc with a known magic
+    Pyc,
+}
+
+impl CarveSignature {
+    fn detect(buffer: &[u8]) -> Option<Self> {
+        let signature = if buffer.starts_with(b"CCCCZZZ4") {
+            CarveSignature::CompressedLz4
+        } else if buffer.starts_with(b"CCCCLZMA") {
+            CarveSignature::CompressedLzma
+        } else if buffer.starts_with(b"CCCC") {
+            CarveSignature::Compressed
+        } else if buffer.starts_with(b"ZZZ4") {
+            CarveSignature::Lz4
+        } else if buffer.starts_with(b"LZMA") {
+            CarveSignature::Lzma
+        } else if buffer.starts_with(b"\xE2\x06") {
+            CarveSignature::MangledZlib
+        } else if buffer.starts_with(&TEXTURE2D_MAGIC.to_le_bytes()) {
+            CarveSignature::Texture
+        } else if buffer.get(2..4) == Some(b"\r\n")
+            && matches!(
+                helpers::detect_python_version_from_py_header(buffer),
+                Ok(Some(_))
+            )
+        {
+            CarveSignature::Pyc
+        } else {
+            return None;
+        };
+        Some(signature)
+    }
+
+    /// Whether the payload only decodes with its exact boundaries
+    fn decodes(&self) -> bool {
+        matches!(
+            self,
+            CarveSignature::Lz4
+                | CarveSignature::CompressedLz4
+                | CarveSignature::CompressedLzma
+                | CarveSignature::MangledZlib
+        )
+    }
+
+    /// Two byte magics show up in any larger shard by chance, these only count
+    /// as an entry if the payload decodes
+    fn is_weak(&self) -> bool {
+        matches!(self, CarveSignature::MangledZlib)
+    }
+
+    fn extension(&self) -> Option<&'static str> {
+        match self {
+            CarveSignature::Texture => Some("tex"),
+            CarveSignature::Pyc => Some("pyc"),
+            _ => None,
+        }
+    }
+}
+
+/// An entry recovered from a shard without its index
+#[derive(Debug, Clone, Serialize)]
+pub struct CarvedEntry {
+    pub shard: u32,
+    pub offset: u32,
+    pub size: u32,
+    /// `None` for data between two recognised entries
+    pub signature: Option<CarveSignature>,
+    /// The payload decoded with exactly these boundaries, otherwise the entry simply
+    /// runs up to the next one
+    pub exact: bool,
+    /// `shard{n}/{offset}.{ext}`, the extension is sniffed from the decoded data
+    pub name: String,
+}
+
+/// Recovers entries from `.mpk` shards whose `.mpkinfo` is lost.
+///
+/// Shards are scanned for the signatures of the known containers and file types.
+/// Containers that can be decoded get their exact end by trying to decode them up
+/// to each following signature, everything else runs up to the next signature.
+/// Data that isn't covered by any signature is kept as separate raw entries.
+///
+/// Every offset of a shard is checked for a signature. A container is decoded once
+/// for each of up to [`MPKCarver::set_lookahead`] following signatures, and every
+/// `E2 06` is inflated once for each of the 37 heads the mangling can leave, so large
+/// shards take a while. Raw data behind a container has no signature and ends up in it.
+#[derive(Debug)]
+pub struct MPKCarver {
+    codecs: CodecRegistry,
+    signatures: SignatureRegistry,
+    lookahead: usize,
+}
+
+impl Default for MPKCarver {
+    fn default() -> Self {
+        Self {
+            codecs: CodecRegistry::default(),
+            signatures: SignatureRegistry::default(),
+            lookahead: DEFAULT_LOOKAHEAD,
+        }
+    }
+}
+
+impl MPKCarver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of following signatures tried as the end of a container, lower values
+    /// carve faster but guess more boundaries
+    pub fn set_lookahead(&mut self, lookahead: usize) {
+        self.lookahead = lookahead.max(1);
+    }
+
+    /// Carves `basename.mpk` and the `basename1.mpk`, `basename2.mpk`, ... next to it,
+    /// up to the first shard that doesn't exist
+    pub fn carve<P: AsRef<Path>>(&self, first_shard: P) -> anyhow::Result<MPKCarve> {
+        let info_path = first_shard.as_ref().with_extension("mpkinfo");
+        let mut entries = Vec::new();
+        for file_number in 0.. {
+            let path = shard_path(&info_path, file_number);
+            let file = match std::fs::File::open(&path) {
+                Ok(file) => file,
+                Err(err) if file_number > 0 && err.kind() == std::io::ErrorKind::NotFound => break,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Failed to open shard {}", path.display()))
+                }
+            };
+            // SAFETY: see `Shard::map`
+            let data = unsafe { Mmap::map(&file) }
+                .with_context(|| format!("Failed to map shard {}", path.display()))?;
+            if data.len() > u32::MAX as usize {
+                anyhow::bail!(
+                    "shard {} is too large for the 32 bit offsets of an index",
+                    path.display()
+                );
+            }
+            entries.extend(self.carve_shard(file_number, &data));
+        }
+        Ok(MPKCarve { info_path, entries })
+    }
+
+    /// Carves the contents of a single shard
+    pub fn carve_shard(&self, shard: u32, data: &[u8]) -> Vec<CarvedEntry> {
+        let candidates: Vec<(usize, CarveSignature)> = (0..data.len())
+            .filter_map(|offset| {
+                let signature = CarveSignature::detect(&data[offset..])?;
+                // The codec magic behind `CCCC` belongs to the `CCCC` entry
+                let behind_cccc = offset >= 4 && &data[offset - 4..offset] == b"CCCC";
+                if behind_cccc && matches!(signature, CarveSignature::Lz4 | CarveSignature::Lzma) {
+                    return None;
+                }
+                Some((offset, signature))
+            })
+            .collect();
+
+        let mut entries = Vec::new();
+        let mut position = 0;
+        for (i, &(start, signature)) in candidates.iter().enumerate() {
+            if start < position {
+                continue;
+            }
+
+            let mut ends = candidates[i + 1..]
+                .iter()
+                .map(|(offset, _)| *offset)
+                .take(self.lookahead)
+                .chain(std::iter::once(data.len()));
+            let exact_end = match signature {
+                // zlib streams know where they end, no need to try the candidates
+                CarveSignature::MangledZlib => MangledZlib
+                    .stream_len(&data[start..], self.codecs.max_decoded_size())
+                    .map(|len| start + len),
+                _ if signature.decodes() => {
+                    ends.clone().find(|end| self.decodes(&data[start..*end]))
+                }
+                _ => None,
+            };
+            let end = match exact_end {
+                Some(end) => end,
+                None if signature.is_weak() => continue,
+                None => ends.next().unwrap_or(data.len()),
+            };
+
+            if start > position {
+                entries.push(self.entry(shard, data, position, start, None, false));
+            }
+            entries.push(self.entry(
+                shard,
+                data,
+                start,
+                end,
+                Some(signature),
+                exact_end.is_some(),
+            ));
+            position = end;
+        }
+        if position < data.len() {
+            entries.push(self.entry(shard, data, position, data.len(), None, false));
+        }
+        entries
+    }
+
+    fn decodes(&self, buffer: &[u8]) -> bool {
+        let Some(codec) = self.codecs.detect(buffer) else {
+            return false;
+        };
+        if codec
+            .decoded_size(buffer)
+            .is_some_and(|size| size > buffer.len().saturating_mul(MAX_EXPANSION))
+        {
+            return false;
+        }
+        codec
+            .decode_limited(buffer, self.codecs.max_decoded_size())
+            .is_ok()
+    }
+
+    fn entry(
+        &self,
+        shard: u32,
+        data: &[u8],
+        start: usize,
+        end: usize,
+        signature: Option<CarveSignature>,
+        exact: bool,
+    ) -> CarvedEntry {
+        let buffer = &data[start..end];
+        let extension = signature
+            .and_then(|signature| signature.extension())
+            .or_else(|| {
+                let decoded = match self.codecs.detect(buffer) {
+                    Some(codec) if exact => codec
+                        .decode_limited(buffer, self.codecs.max_decoded_size())
+                        .ok()?,
+                    Some(_) => return None,
+                    None => buffer.to_vec(),
+                };
+                self.signatures.extension(&decoded)
+            })
+            .unwrap_or("dat");
+        CarvedEntry {
+            shard,
+            offset: start as u32,
+            size: (end - start) as u32,
+            signature,
+            exact,
+            name: format!("shard{}/{:08x}.{}", shard, start, extension),
+        }
+    }
+}
+
+/// Entries carved from the shards next to `info_path`
+#[derive(Debug, Clone)]
+pub struct MPKCarve {
+    info_path: PathBuf,
+    entries: Vec<CarvedEntry>,
+}
+
+impl MPKCarve {
+    /// Where the rebuilt `.mpkinfo` belongs, next to the shards
+    pub fn info_path(&self) -> &Path {
+        &self.info_path
+    }
+
+    pub fn entries(&self) -> &[CarvedEntry] {
+        &self.entries
+    }
+
+    fn index(&self) -> (MPKFileHeader, Vec<MPKFileEntry>) {
+        let header = MPKFileHeader {
+            version: 1,
+            file_count: self.entries.len() as u32,
+        };
+        let files = self
+            .entries
+            .iter()
+            .map(|entry| {
+                MPKFileEntry::V1(MPKFileEntryV1 {
+                    name: entry.name.as_bytes().to_vec(),
+                    offset: entry.offset,
+                    size: entry.size,
+                    is_folder: false,
+                    file_number: entry.shard,
+                })
+            })
+            .collect();
+        (header, files)
+    }
+
+    /// Reader over the carved entries, so they can be listed, verified and extracted
+    /// like the entries of a real index
+    pub fn reader(&self) -> MPKFileReader {
+        let (header, files) = self.index();
+        MPKFileReader::from_entries(&self.info_path, header, files)
+    }
+
+    /// Writes a version 1 `.mpkinfo` for the carved entries next to the shards,
+    /// an existing index is never overwritten
+    pub fn write_index(&self) -> anyhow::Result<()> {
+        let (header, files) = self.index();
+        let file = std::fs::File::create_new(&self.info_path).with_context(|| {
+            format!(
+                "Failed to create .mpkinfo file {}",
+                self.info_path.display()
+            )
+        })?;
+        let mut writer = BufWriter::new(file);
+        header.write_header(&mut writer)?;
+        for file in &files {
+            file.write_to(&mut writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use try_insert_ext::EntryInsertExt;
+
+use crate::{MPKFileEntry, MPKFileReader, Shard};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MPKDiffEntry {
+    pub index: usize,
+    pub shard: u32,
+    /// Size of the stored payload, or of the decoded content when comparing content
+    pub size: u64,
+    /// Name hash, only stored by version 2 indices
+    pub hash: Option<u32>,
+    /// SHA-1 of the stored payload, or of the decoded content when comparing content
+    pub digest: Option<String>,
+    /// Why the payload couldn't be read or decoded, there is no digest then
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MPKDiffChange {
+    pub name: String,
+    pub old: MPKDiffEntry,
+    pub new: MPKDiffEntry,
+}
+
+/// Differences between two archives, entries are matched by name.
+///
+/// Version 2 indices don't store names, their entries are matched by hash and extension.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MPKDiff {
+    pub added: BTreeMap<String, MPKDiffEntry>,
+    pub removed: BTreeMap<String, MPKDiffEntry>,
+    pub changed: Vec<MPKDiffChange>,
+    /// Entries in both archives that couldn't be compared because one side failed to read
+    pub failed: Vec<MPKDiffChange>,
+}
+
+impl MPKDiff {
+    /// Compares `old` against `new`.
+    ///
+    /// Entries count as changed if the size or SHA-1 of their stored payload differs,
+    /// with `compare_content` every payload is decoded and the content compared instead,
+    /// so entries that were only stored differently don't count.
+    pub fn compute(
+        old: &MPKFileReader,
+        new: &MPKFileReader,
+        compare_content: bool,
+    ) -> anyhow::Result<MPKDiff> {
+        let mut old_entries = old.diff_entries(compare_content)?;
+        let mut diff = MPKDiff::default();
+        for (name, new_entry) in new.diff_entries(compare_content)? {
+            match old_entries.remove(&name) {
+                Some(old_entry) if old_entry.error.is_some() || new_entry.error.is_some() => {
+                    diff.failed.push(MPKDiffChange {
+                        name,
+                        old: old_entry,
+                        new: new_entry,
+                    });
+                }
+                Some(old_entry) => {
+                    if old_entry.size != new_entry.size || old_entry.digest != new_entry.digest {
+                        diff.changed.push(MPKDiffChange {
+                            name,
+                            old: old_entry,
+                            new: new_entry,
+                        });
+                    }
+                }
+                None => {
+                    diff.added.insert(name, new_entry);
+                }
+            }
+        }
+        diff.removed = old_entries;
+        Ok(diff)
+    }
+
+    /// Whether the archives are known to be the same, entries that couldn't be
+    /// compared count as a difference
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.failed.is_empty()
+    }
+
+    /// Indices of all added and changed entries in the new archive
+    pub fn new_indices(&self) -> Vec<usize> {
+        self.added
+            .values()
+            .chain(self.changed.iter().map(|change| &change.new))
+            .map(|entry| entry.index)
+            .collect()
+    }
+}
+
+impl MPKFileReader {
+    fn diff_entries(
+        &self,
+        compare_content: bool,
+    ) -> anyhow::Result<BTreeMap<String, MPKDiffEntry>> {
+        let mut mpk_map = HashMap::new();
+        let mut entries = BTreeMap::new();
+        for (index, file) in self.files.iter().enumerate() {
+            if file.is_folder() {
+                continue;
+            }
+
+            let (size, digest, error) = match self.diff_digest(&mut mpk_map, file, compare_content)
+            {
+                Ok((size, digest)) => (size, Some(digest), None),
+                Err(err) => (file.size() as u64, None, Some(format!("{:#}", err))),
+            };
+
+            let (name, hash) = match file {
+                MPKFileEntry::V1(entry) => (entry.name().into_owned(), None),
+                MPKFileEntry::V2(entry) => (
+                    format!("{}.{}", entry.hash, String::from_utf8_lossy(&entry.name)),
+                    Some(entry.hash),
+                ),
+            };
+            entries.insert(
+                name,
+                MPKDiffEntry {
+                    index,
+                    shard: file.file_number(),
+                    size,
+                    hash,
+                    digest,
+                    error,
+                },
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Size and SHA-1 of the stored payload of `file`, or of its decoded content
+    fn diff_digest(
+        &self,
+        mpk_map: &mut HashMap<u32, Shard>,
+        file: &MPKFileEntry,
+        compare_content: bool,
+    ) -> anyhow::Result<(u64, String)> {
+        let file_number = file.file_number();
+        let mpk_file = mpk_map
+            .entry(file_number)
+            .or_try_insert_with(|| self.open_shard(file_number))?;
+        let file_buffer = self.read_entry(mpk_file, file)?;
+        let digest = |data: &[u8]| (data.len() as u64, format!("{:x}", Sha1::digest(data)));
+        if !compare_content {
+            return Ok(digest(&file_buffer));
+        }
+        let (file_buffer, _) = self.decode_entry(file, &file_buffer)?;
+        Ok(digest(&file_buffer))
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{MPKFileEntry, MPKFileReader};
+
+/// Read only view of a single index entry, see [`MPKFileReader::entries`]
+#[derive(Debug, Copy, Clone)]
+pub struct MPKEntry<'a> {
+    reader: &'a MPKFileReader,
+    index: usize,
+    file: &'a MPKFileEntry,
+}
+
+impl<'a> MPKEntry<'a> {
+    pub(crate) fn new(reader: &'a MPKFileReader, index: usize, file: &'a MPKFileEntry) -> Self {
+        Self {
+            reader,
+            index,
+            file,
+        }
+    }
+
+    /// Position of the entry in the index
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Name in the index, or the recovered path of version 2 entries after
+    /// [`MPKFileReader::resolve_names`]. Extraction may still detect a better one.
+    pub fn name(&self) -> String {
+        self.reader.entry_name(self.file)
+    }
+
+    /// The record as stored in the index
+    pub fn record(&self) -> &'a MPKFileEntry {
+        self.file
+    }
+
+    /// Version of the index the entry comes from, `1` or `2`
+    pub fn version(&self) -> u32 {
+        self.file.version()
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.file.is_folder()
+    }
+
+    /// Shard the payload is stored in, `0` is `basename.mpk`
+    pub fn shard(&self) -> u32 {
+        self.file.file_number()
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.file.offset()
+    }
+
+    /// Size of the payload inside the shard
+    pub fn size(&self) -> u32 {
+        self.file.size()
+    }
+
+    /// Flags word as stored in the index, see [`MPKFileEntry::flags`]
+    pub fn flags(&self) -> u32 {
+        self.file.flags()
+    }
+
+    /// Name hash, only stored by version 2 indices
+    pub fn hash(&self) -> Option<u32> {
+        self.file.hash()
+    }
+
+    /// Reads and decodes the entry, see [`MPKFileReader::open_entry`]
+    pub fn open(&self) -> anyhow::Result<MPKEntryReader> {
+        self.reader.open_entry(self.index)
+    }
+}
+
+/// A single decoded entry opened through [`crate::MPKFileReader::open_entry`].
+///
+/// The container (`ZZZ4`, `CCCC`, mangled zlib, ...) is already undone, reading
+/// yields the same bytes extraction would write to disk.
+#[derive(Debug)]
+pub struct MPKEntryReader {
+    name: String,
+    data: std::io::Cursor<Vec<u8>>,
+}
+
+impl MPKEntryReader {
+    pub(crate) fn new(name: String, data: Vec<u8>) -> Self {
+        Self {
+            name,
+            data: std::io::Cursor::new(data),
+        }
+    }
+
+    /// The name extraction would write this entry to
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Decoded size in bytes
+    pub fn len(&self) -> usize {
+        self.data.get_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data.into_inner()
+    }
+}
+
+impl Read for MPKEntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl Seek for MPKEntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
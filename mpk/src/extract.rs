@@ -0,0 +1,520 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Context;
+use indicatif::ProgressBar;
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use crate::incremental::IncrementalState;
+use crate::sink::{write_file, ExtractSink};
+use crate::{
+    shard_path, ExtractFilter, ExtractFormat, ExtractManifest, MPKError, MPKFileEntry,
+    MPKFileReader, ManifestEntry,
+};
+
+/// Stored bytes decoded per batch before the batch is written out, bounds the
+/// memory held by decoded entries waiting to be written
+const BATCH_SIZE: u64 = 256 << 20;
+
+/// Settings for [`MPKFileReader::extract_files_with`]
+#[derive(Debug, Default, Clone)]
+pub struct ExtractOptions {
+    /// Entries to extract, everything by default
+    pub filter: ExtractFilter,
+    /// Worker threads decoding and writing entries, one per core by default
+    pub threads: Option<usize>,
+    /// Record entries failing to extract in the summary and carry on with the rest,
+    /// instead of stopping at the first one
+    pub keep_going: bool,
+    /// Write an [`ExtractManifest`] of everything extracted to this path
+    pub manifest: Option<PathBuf>,
+    /// Write a directory tree or a single archive
+    pub format: ExtractFormat,
+    /// Leave outputs of an earlier extraction into the same directory alone if they
+    /// are still up to date. The state is kept in the manifest, at
+    /// [`ExtractManifest::default_path`] unless [`ExtractOptions::manifest`] is set
+    pub incremental: bool,
+    /// With `incremental`, delete outputs of the earlier extraction whose entries
+    /// are gone from the archive
+    pub prune: bool,
+    /// Decoded bytes waiting to be written that extraction may hold at once, on top
+    /// of the entries being decoded. Batches are cut short once they reach it but
+    /// always keep their first entry. Without it only the stored size of a batch is
+    /// bounded, see [`messiah_codec::CodecRegistry::set_max_decoded_size`] for the
+    /// limit per entry
+    pub max_memory: Option<usize>,
+}
+
+impl ExtractOptions {
+    /// Whether entries need a [`ManifestEntry`]
+    pub(crate) fn records(&self) -> bool {
+        self.manifest.is_some() || self.incremental
+    }
+}
+
+/// An entry [`MPKFileReader::extract_files_with`] failed to extract
+#[derive(Debug)]
+pub struct ExtractFailure {
+    pub index: usize,
+    pub name: String,
+    pub error: anyhow::Error,
+}
+
+/// What [`MPKFileReader::extract_files_with`] did
+#[derive(Debug, Default)]
+pub struct ExtractSummary {
+    /// Files written, entries overwritten by a later one with the same name included
+    pub extracted: usize,
+    /// Entries left out by the filter
+    pub skipped: usize,
+    /// Entries that failed, only ever filled with [`ExtractOptions::keep_going`]
+    pub failures: Vec<ExtractFailure>,
+    /// Files left alone because they were already up to date, only with
+    /// [`ExtractOptions::incremental`]
+    pub unchanged: usize,
+    /// Outputs deleted because their entries are gone, only with [`ExtractOptions::prune`]
+    pub removed: usize,
+}
+
+/// Read only memory maps of the shards of an archive
+pub(crate) struct MappedShards {
+    maps: HashMap<u32, Mmap>,
+    /// Shards that don't exist, entries stored in them fail individually
+    missing: HashMap<u32, PathBuf>,
+    /// Shards compressed inside their package, their entries fail individually as well
+    compressed: HashMap<u32, PathBuf>,
+}
+
+impl MappedShards {
+    /// Maps every shard of `reader` in `file_numbers`
+    pub(crate) fn open(
+        reader: &MPKFileReader,
+        file_numbers: impl IntoIterator<Item = u32>,
+    ) -> anyhow::Result<Self> {
+        let mut maps = HashMap::new();
+        let mut missing = HashMap::new();
+        let mut compressed = HashMap::new();
+        for file_number in file_numbers {
+            let path = shard_path(&reader.path, file_number);
+            let shard = match reader.open_shard(file_number) {
+                Ok(shard) => shard,
+                Err(MPKError::MissingShard(path)) => {
+                    missing.insert(file_number, path);
+                    continue;
+                }
+                Err(MPKError::CompressedShard(path)) => {
+                    compressed.insert(file_number, path);
+                    continue;
+                }
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Failed to open shard {}", path.display()))
+                }
+            };
+            let map = shard
+                .map()
+                .with_context(|| format!("Failed to map shard {}", path.display()))?;
+            maps.insert(file_number, map);
+        }
+        Ok(Self {
+            maps,
+            missing,
+            compressed,
+        })
+    }
+
+    /// The stored payload of `file`, without copying it
+    pub(crate) fn payload(&self, file: &MPKFileEntry, name: &str) -> Result<&[u8], MPKError> {
+        let file_number = file.file_number();
+        if let Some(path) = self.missing.get(&file_number) {
+            return Err(MPKError::MissingShard(path.clone()));
+        }
+        if let Some(path) = self.compressed.get(&file_number) {
+            return Err(MPKError::CompressedShard(path.clone()));
+        }
+        let start = file.offset() as usize;
+        self.maps
+            .get(&file_number)
+            .zip(start.checked_add(file.size() as usize))
+            .and_then(|(map, end)| map.get(start..end))
+            .ok_or_else(|| MPKError::EntryOutOfRange(name.to_string()))
+    }
+}
+
+/// What extraction does with a single entry, paths are relative to the output
+pub(crate) enum Extracted<'a> {
+    Skipped,
+    Folder(String, Option<ManifestEntry>),
+    File(String, Cow<'a, [u8]>, Option<ManifestEntry>),
+    /// The output already holds the entry
+    Unchanged(String, Option<ManifestEntry>),
+}
+
+impl MPKFileReader {
+    pub fn extract_files<P: AsRef<Path>>(&self, out_dir: P) -> anyhow::Result<ExtractSummary> {
+        self.extract_files_with(out_dir, &ExtractOptions::default())
+    }
+
+    /// Extracts the entries selected by `options.filter` into `out`, a directory or
+    /// an archive depending on `options.format`.
+    ///
+    /// Shards are memory mapped and entries are decoded and written on a thread pool,
+    /// the result is the same as extracting them one after another in index order,
+    /// including later entries overwriting earlier ones with the same name.
+    pub fn extract_files_with<P: AsRef<Path>>(
+        &self,
+        out: P,
+        options: &ExtractOptions,
+    ) -> anyhow::Result<ExtractSummary> {
+        let filter = &options.filter;
+        let incremental = IncrementalState::load(out.as_ref(), options)?;
+
+        let selected: Vec<(usize, &MPKFileEntry)> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(index, file)| filter.matches_index(*index, file))
+            .collect();
+        let shards = MappedShards::open(
+            self,
+            selected
+                .iter()
+                .filter(|(_, file)| !file.is_folder())
+                .map(|(_, file)| file.file_number())
+                .collect::<BTreeSet<_>>(),
+        )?;
+
+        let selected: Vec<(usize, u64)> = selected
+            .iter()
+            .map(|(index, file)| (*index, file.size() as u64))
+            .collect();
+        extract_parallel(
+            &self.path,
+            out.as_ref(),
+            self.files.len(),
+            &selected,
+            options,
+            incremental.as_ref(),
+            |index| self.extract_entry(&shards, options, incremental.as_ref(), index),
+            |index| self.entry_name(&self.files[index]),
+        )
+    }
+
+    /// Decodes a single entry and works out where it goes
+    fn extract_entry<'a>(
+        &self,
+        shards: &'a MappedShards,
+        options: &ExtractOptions,
+        incremental: Option<&IncrementalState>,
+        index: usize,
+    ) -> anyhow::Result<Extracted<'a>> {
+        let filter = &options.filter;
+        let file = &self.files[index];
+        if file.is_folder() {
+            let record = options
+                .records()
+                .then(|| ManifestEntry::folder(index, file.name()));
+            return Ok(Extracted::Folder(file.name(), record));
+        }
+
+        let name = self.entry_name(file);
+        let file_buffer = shards.payload(file, &name)?;
+
+        // Unless the container hides it the name is known before decoding
+        let name_known = self
+            .codecs
+            .detect(file_buffer)
+            .is_none_or(|codec| !codec.sniff_name());
+        if name_known && !filter.matches_name(&name) {
+            return Ok(Extracted::Skipped);
+        }
+
+        let (flags, hash) = match file {
+            MPKFileEntry::V1(_) => (None, None),
+            MPKFileEntry::V2(entry) => (Some(entry.flags), Some(entry.hash)),
+        };
+        if let Some(mut record) =
+            incremental.and_then(|state| state.unchanged(&name, file_buffer, filter))
+        {
+            record.index = index;
+            record.shard = file.file_number();
+            record.offset = file.offset() as u64;
+            record.flags = flags;
+            record.hash = hash;
+            return Ok(Extracted::Unchanged(record.path.clone(), Some(record)));
+        }
+
+        let stored = file_buffer;
+        let (file_buffer, alt_file_name) = self.decode_entry(file, stored)?;
+        let file_name = alt_file_name.unwrap_or_else(|| name.clone());
+        if !filter.matches_name(&file_name) || !filter.matches_content(&file_buffer) {
+            return Ok(Extracted::Skipped);
+        }
+
+        let record = options.records().then(|| {
+            let mut record = ManifestEntry::file(
+                index,
+                name,
+                file_name.clone(),
+                file.file_number(),
+                file.offset() as u64,
+                self.codecs.container_name(stored),
+                stored,
+                &file_buffer,
+            );
+            record.flags = flags;
+            record.hash = hash;
+            record
+        });
+        if incremental.is_some_and(|state| state.is_written(&file_name, &file_buffer)) {
+            return Ok(Extracted::Unchanged(file_name, record));
+        }
+        Ok(Extracted::File(file_name, file_buffer, record))
+    }
+}
+
+/// Decodes the `selected` entries, given by index and stored size, on a thread pool
+/// and writes them out to `out` batch by batch in index order.
+///
+/// `total` is the number of entries in the archive, everything not selected counts
+/// as skipped by the filter. `archive` is only used for the manifest.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn extract_parallel<'a>(
+    archive: &Path,
+    out: &Path,
+    total: usize,
+    selected: &[(usize, u64)],
+    options: &ExtractOptions,
+    incremental: Option<&IncrementalState>,
+    extract_entry: impl Fn(usize) -> anyhow::Result<Extracted<'a>> + Sync,
+    entry_name: impl Fn(usize) -> String + Sync,
+) -> anyhow::Result<ExtractSummary> {
+    let bar = ProgressBar::new(total as u64);
+    bar.inc((total - selected.len()) as u64);
+
+    let mut summary = ExtractSummary {
+        skipped: total - selected.len(),
+        ..Default::default()
+    };
+
+    let mut manifest = ExtractManifest {
+        archive: archive.to_path_buf(),
+        entries: Vec::new(),
+    };
+
+    let mut sink = ExtractSink::create(out, options.format)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(0))
+        .build()?;
+    pool.install(|| {
+        let mut batch_start = 0;
+        while batch_start < selected.len() {
+            let mut batch_end = batch_start;
+            let mut batch_size = 0;
+            while batch_end < selected.len()
+                && (batch_end == batch_start || batch_size < BATCH_SIZE)
+            {
+                batch_size += selected[batch_end].1;
+                batch_end += 1;
+            }
+
+            let first = selected[batch_start].0;
+            let held = AtomicUsize::new(0);
+            let mut results: Vec<_> = selected[batch_start..batch_end]
+                .par_iter()
+                .map(|(index, _)| {
+                    let Some(limit) = options.max_memory else {
+                        let result = extract_entry(*index);
+                        bar.inc(1);
+                        return (*index, Some(result));
+                    };
+                    // Once the batch is full the remaining entries wait for the next one
+                    if *index != first && held.load(Ordering::Relaxed) > limit {
+                        return (*index, None);
+                    }
+                    let result = extract_entry(*index);
+                    if let Ok(Extracted::File(_, Cow::Owned(data), _)) = &result {
+                        let before = held.fetch_add(data.len(), Ordering::Relaxed);
+                        if *index != first && before + data.len() > limit {
+                            return (*index, None);
+                        }
+                    }
+                    bar.inc(1);
+                    (*index, Some(result))
+                })
+                .collect();
+            // Everything behind the first deferred entry is decoded again, so
+            // entries are still written in index order
+            if let Some(deferred) = results.iter().position(|(_, result)| result.is_none()) {
+                let redone = results[deferred..]
+                    .iter()
+                    .filter(|(_, result)| result.is_some());
+                bar.set_position(bar.position() - redone.count() as u64);
+                results.truncate(deferred);
+                batch_end = batch_start + deferred;
+            }
+            let results: Vec<_> = results
+                .into_iter()
+                .filter_map(|(index, result)| Some((index, result?)))
+                .collect();
+            write_batch(
+                results,
+                options.keep_going,
+                &mut sink,
+                &mut summary,
+                &mut manifest.entries,
+                &entry_name,
+            )?;
+
+            batch_start = batch_end;
+        }
+        anyhow::Ok(())
+    })?;
+    sink.finish()?;
+
+    bar.finish();
+
+    if let Some(state) = incremental {
+        let mut entry_names = HashMap::new();
+        for index in 0..total {
+            entry_names.entry(entry_name(index)).or_insert(index);
+        }
+        summary.removed = state.finish(&mut manifest, &entry_names, options.prune)?;
+    }
+
+    let manifest_path = options
+        .manifest
+        .as_deref()
+        .or(incremental.map(|state| state.manifest_path()));
+    if let Some(path) = manifest_path {
+        // Only the last entry written to a path is on disk
+        let mut written = HashSet::new();
+        for record in manifest.entries.iter_mut().rev() {
+            record.overwritten = !record.is_folder && !written.insert(record.path.clone());
+        }
+        manifest.write(path)?;
+    }
+
+    Ok(summary)
+}
+
+/// Writes the decoded entries of a batch, stopping where serial extraction would
+/// have hit the first error unless `keep_going` is set
+fn write_batch(
+    results: Vec<(usize, anyhow::Result<Extracted>)>,
+    keep_going: bool,
+    sink: &mut ExtractSink,
+    summary: &mut ExtractSummary,
+    manifest: &mut Vec<ManifestEntry>,
+    entry_name: &(impl Fn(usize) -> String + Sync),
+) -> anyhow::Result<()> {
+    let mut failed = HashSet::new();
+    let mut fail = |index: usize, error: anyhow::Error| -> anyhow::Result<()> {
+        if !keep_going {
+            return Err(error);
+        }
+        failed.insert(index);
+        summary.failures.push(ExtractFailure {
+            index,
+            name: entry_name(index),
+            error,
+        });
+        Ok(())
+    };
+
+    let mut extracted = Vec::with_capacity(results.len());
+    let mut error = None;
+    for (index, result) in results {
+        match result {
+            Ok(entry) => extracted.push((index, entry)),
+            Err(err) if keep_going => fail(index, err)?,
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        }
+    }
+
+    // Only the last entry written to a path survives serial extraction. Sinks that can't
+    // replace files get every entry, so duplicates fail the same way in every batch.
+    let replaces_files = sink.replaces_files();
+    let mut last_write = HashMap::new();
+    for (position, (index, entry)) in extracted.iter().enumerate() {
+        match entry {
+            Extracted::Folder(path, _) => {
+                if let Err(err) = sink.add_folder(path) {
+                    fail(*index, err)?;
+                }
+            }
+            Extracted::File(path, _, _) | Extracted::Unchanged(path, _) => {
+                last_write.insert(path, position);
+            }
+            Extracted::Skipped => {}
+        }
+    }
+
+    let files: Vec<_> = extracted
+        .iter()
+        .enumerate()
+        .filter_map(|(position, (index, entry))| match entry {
+            Extracted::File(path, file_buffer, _) => Some((
+                *index,
+                path,
+                file_buffer,
+                !replaces_files || last_write.get(path) == Some(&position),
+            )),
+            _ => None,
+        })
+        .collect();
+    let written: Vec<_> = match sink.directory() {
+        Some(out_dir) => files
+            .par_iter()
+            .map(|(index, path, file_buffer, last)| match last {
+                true => (*index, write_file(out_dir, path, file_buffer)),
+                false => (*index, Ok(())),
+            })
+            .collect(),
+        // Archive members go in one after another in index order
+        None => files
+            .iter()
+            .map(|(index, path, file_buffer, last)| match last {
+                true => (*index, sink.add_file(path, file_buffer)),
+                false => (*index, Ok(())),
+            })
+            .collect(),
+    };
+    for (index, result) in written {
+        match result {
+            Ok(()) => summary.extracted += 1,
+            Err(err) => fail(index, err)?,
+        }
+    }
+    summary.skipped += extracted
+        .iter()
+        .filter(|(_, entry)| matches!(entry, Extracted::Skipped))
+        .count();
+    summary.unchanged += extracted
+        .iter()
+        .filter(|(_, entry)| matches!(entry, Extracted::Unchanged(..)))
+        .count();
+    manifest.extend(
+        extracted
+            .into_iter()
+            .filter(|(index, _)| !failed.contains(index))
+            .filter_map(|(_, entry)| match entry {
+                Extracted::Folder(_, record)
+                | Extracted::File(_, _, record)
+                | Extracted::Unchanged(_, record) => record,
+                Extracted::Skipped => None,
+            }),
+    );
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
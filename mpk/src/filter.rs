@@ -0,0 +1,259 @@
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+
+use crate::MPKFileEntry;
+
+/// Selects which entries extraction writes.
+///
+/// Every configured criterion has to match. Globs and regexes are matched against
+/// the name extraction writes an entry to, so `Script/Python/**` works for scripts
+/// that are only named after decoding them.
+#[derive(Debug, Default, Clone)]
+pub struct ExtractFilter {
+    include: Vec<GlobMatcher>,
+    exclude: Vec<GlobMatcher>,
+    regexes: Vec<Regex>,
+    extensions: Vec<String>,
+    mime_types: Vec<String>,
+    shards: Vec<u32>,
+    indices: Option<std::collections::HashSet<usize>>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+}
+
+impl ExtractFilter {
+    /// Whether no criterion is configured and every entry passes
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.regexes.is_empty()
+            && self.extensions.is_empty()
+            && self.mime_types.is_empty()
+            && self.shards.is_empty()
+            && self.indices.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+    }
+
+    /// Only extract entries matching at least one of the include globs, `**` crosses folders
+    pub fn add_include(&mut self, pattern: &str) -> anyhow::Result<()> {
+        self.include.push(Self::glob(pattern)?);
+        Ok(())
+    }
+
+    /// Skip entries matching any exclude glob
+    pub fn add_exclude(&mut self, pattern: &str) -> anyhow::Result<()> {
+        self.exclude.push(Self::glob(pattern)?);
+        Ok(())
+    }
+
+    /// Only extract entries matching at least one of the regexes
+    pub fn add_regex(&mut self, pattern: &str) -> anyhow::Result<()> {
+        self.regexes.push(Regex::new(pattern)?);
+        Ok(())
+    }
+
+    /// Only extract entries with one of the given extensions, as detected during extraction
+    pub fn add_extension(&mut self, extension: &str) {
+        self.extensions
+            .push(extension.trim_start_matches('.').to_lowercase());
+    }
+
+    /// Only extract entries whose decoded content sniffs as one of the given mime types
+    pub fn add_mime_type(&mut self, mime_type: &str) {
+        self.mime_types.push(mime_type.to_string());
+    }
+
+    /// Only extract entries stored in one of the given shards
+    pub fn add_shard(&mut self, file_number: u32) {
+        self.shards.push(file_number);
+    }
+
+    /// Only extract the entries at the given positions of the index
+    pub fn add_indices<I: IntoIterator<Item = usize>>(&mut self, indices: I) {
+        self.indices
+            .get_or_insert_with(Default::default)
+            .extend(indices);
+    }
+
+    /// Limit the size of the entry inside its shard, both bounds are inclusive
+    pub fn set_size_range(&mut self, min_size: Option<u64>, max_size: Option<u64>) {
+        self.min_size = min_size;
+        self.max_size = max_size;
+    }
+
+    fn glob(pattern: &str) -> anyhow::Result<GlobMatcher> {
+        Ok(GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()?
+            .compile_matcher())
+    }
+
+    /// Checks everything known from the index alone
+    pub(crate) fn matches_index(&self, index: usize, file: &MPKFileEntry) -> bool {
+        if file.is_folder() {
+            // Folders of filtered extractions get created for the files written into them
+            return self.is_empty();
+        }
+        self.matches_location(index, file.file_number(), file.size() as u64)
+    }
+
+    /// Checks index, shard and stored size of a file entry
+    pub(crate) fn matches_location(&self, index: usize, shard: u32, size: u64) -> bool {
+        (self.shards.is_empty() || self.shards.contains(&shard))
+            && self
+                .indices
+                .as_ref()
+                .is_none_or(|indices| indices.contains(&index))
+            && self.min_size.is_none_or(|min_size| size >= min_size)
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+    }
+
+    /// Checks the name extraction writes an entry to
+    pub(crate) fn matches_name(&self, name: &str) -> bool {
+        let extension = std::path::Path::new(name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(name)))
+            && !self.exclude.iter().any(|glob| glob.is_match(name))
+            && (self.regexes.is_empty() || self.regexes.iter().any(|regex| regex.is_match(name)))
+            && (self.extensions.is_empty()
+                || extension.is_some_and(|extension| self.extensions.contains(&extension)))
+    }
+
+    /// Checks the decoded content
+    pub(crate) fn matches_content(&self, data: &[u8]) -> bool {
+        self.mime_types.is_empty()
+            || self
+                .mime_types
+                .iter()
+                .any(|mime_type| mime_type == tree_magic_mini::from_u8(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MPKFileEntryV1;
+
+    fn entry(is_folder: bool, file_number: u32, size: u32) -> MPKFileEntry {
+        MPKFileEntry::V1(MPKFileEntryV1 {
+            name: b"entry".to_vec(),
+            offset: 0,
+            size,
+            is_folder,
+            file_number,
+        })
+    }
+
+    #[test]
+    fn globs() {
+        let mut filter = ExtractFilter::default();
+        filter.add_include("Script/**/*.pyc").unwrap();
+        filter.add_include("*.txt").unwrap();
+        filter.add_exclude("Script/Python/test/**").unwrap();
+        assert!(!filter.is_empty());
+
+        assert!(filter.matches_name("Script/Python/a/b.pyc"));
+        assert!(filter.matches_name("readme.txt"));
+        // `*` doesn't cross folders, `**` does
+        assert!(!filter.matches_name("docs/readme.txt"));
+        assert!(!filter.matches_name("Script/Python/test/a.pyc"));
+        assert!(!filter.matches_name("Script/Python/a.py"));
+        assert!(filter.add_include("[").is_err());
+    }
+
+    #[test]
+    fn regexes() {
+        let mut filter = ExtractFilter::default();
+        filter.add_regex(r"^res/\d+\.").unwrap();
+        filter.add_regex("shader").unwrap();
+        assert!(filter.matches_name("res/12.tex"));
+        assert!(filter.matches_name("a/shader_cache.bin"));
+        assert!(!filter.matches_name("res/a.tex"));
+        assert!(filter.add_regex("(").is_err());
+    }
+
+    #[test]
+    fn extensions() {
+        let mut filter = ExtractFilter::default();
+        filter.add_extension(".PNG");
+        filter.add_extension("tex");
+        assert!(filter.matches_name("a/b.png"));
+        assert!(filter.matches_name("a/b.Tex"));
+        assert!(!filter.matches_name("a/b.dds"));
+        assert!(!filter.matches_name("a/png"));
+    }
+
+    #[test]
+    fn mime_types() {
+        let mut filter = ExtractFilter::default();
+        assert!(filter.matches_content(b"anything"));
+        filter.add_mime_type("image/png");
+        assert!(filter.matches_content(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+        assert!(!filter.matches_content(b"plain text"));
+        // Content doesn't affect anything known before decoding
+        assert!(filter.matches_name("a.txt"));
+        assert!(filter.matches_location(0, 0, 0));
+    }
+
+    #[test]
+    fn locations() {
+        let mut filter = ExtractFilter::default();
+        filter.add_shard(1);
+        filter.add_shard(3);
+        assert!(filter.matches_location(0, 3, 10));
+        assert!(!filter.matches_location(0, 2, 10));
+
+        let mut filter = ExtractFilter::default();
+        filter.add_indices([2, 5]);
+        assert!(filter.matches_location(5, 0, 10));
+        assert!(!filter.matches_location(4, 0, 10));
+        filter.add_indices([]);
+        assert!(!filter.matches_location(4, 0, 10));
+
+        let mut filter = ExtractFilter::default();
+        filter.set_size_range(Some(10), Some(20));
+        assert!(filter.matches_location(0, 0, 10));
+        assert!(filter.matches_location(0, 0, 20));
+        assert!(!filter.matches_location(0, 0, 9));
+        assert!(!filter.matches_location(0, 0, 21));
+        filter.set_size_range(None, Some(20));
+        assert!(filter.matches_location(0, 0, 0));
+    }
+
+    #[test]
+    fn combined() {
+        let mut filter = ExtractFilter::default();
+        filter.add_include("res/**").unwrap();
+        filter.add_extension("tex");
+        filter.add_shard(1);
+        filter.set_size_range(Some(100), None);
+
+        assert!(filter.matches_name("res/a.tex"));
+        assert!(!filter.matches_name("res/a.png"));
+        assert!(!filter.matches_name("other/a.tex"));
+        assert!(filter.matches_location(0, 1, 100));
+        assert!(!filter.matches_location(0, 0, 100));
+        assert!(!filter.matches_location(0, 1, 99));
+    }
+
+    #[test]
+    fn folders() {
+        let filter = ExtractFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches_index(0, &entry(true, 0, 0)));
+        assert!(filter.matches_index(0, &entry(false, 0, 10)));
+
+        // With any criterion set folders are only created for the files written into them
+        let mut filter = ExtractFilter::default();
+        filter.add_include("**").unwrap();
+        assert!(!filter.matches_index(0, &entry(true, 0, 0)));
+        assert!(filter.matches_index(0, &entry(false, 0, 10)));
+
+        let mut filter = ExtractFilter::default();
+        filter.set_size_range(None, Some(5));
+        assert!(!filter.matches_index(0, &entry(true, 0, 0)));
+        assert!(!filter.matches_index(0, &entry(false, 0, 10)));
+    }
+}
@@ -0,0 +1,88 @@
+use serde::Serialize;
+
+use crate::MPKFileReader;
+
+/// A folder of an archive, see [`MPKFileReader::folder_tree`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MPKFolder {
+    /// Path relative to the root of the archive, empty for the root itself
+    pub path: String,
+    /// Index of the folder record, `None` for folders only implied by the paths of
+    /// the entries inside them
+    pub index: Option<usize>,
+    /// Sub folders in the order they first appear in the index
+    pub folders: Vec<MPKFolder>,
+    /// Indices of the files directly inside this folder
+    pub files: Vec<usize>,
+}
+
+impl MPKFolder {
+    /// Last component of the path, empty for the root
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Looks up a folder below this one by its path relative to this folder
+    pub fn find(&self, path: &str) -> Option<&MPKFolder> {
+        components(path).try_fold(self, |folder, name| {
+            folder.folders.iter().find(|child| child.name() == name)
+        })
+    }
+
+    /// This folder and every folder below it, parents before their children
+    pub fn walk(&self) -> Box<dyn Iterator<Item = &MPKFolder> + '_> {
+        Box::new(std::iter::once(self).chain(self.folders.iter().flat_map(MPKFolder::walk)))
+    }
+
+    /// The folder at `path` below this one, created along with its parents as needed
+    fn folder_mut(&mut self, path: &str) -> &mut MPKFolder {
+        components(path).fold(self, |folder, name| {
+            match folder.folders.iter().position(|child| child.name() == name) {
+                Some(position) => &mut folder.folders[position],
+                None => {
+                    let path = match folder.path.is_empty() {
+                        true => name.to_string(),
+                        false => format!("{}/{}", folder.path, name),
+                    };
+                    folder.folders.push(MPKFolder {
+                        path,
+                        ..Default::default()
+                    });
+                    folder.folders.last_mut().unwrap()
+                }
+            }
+        })
+    }
+}
+
+/// Non empty components of an entry path, both `/` and `\` separate folders
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '\\']).filter(|name| !name.is_empty())
+}
+
+impl MPKFileReader {
+    /// Rebuilds the folder hierarchy from the entry paths.
+    ///
+    /// Version 1 folder records anchor their folder even when it is empty, folders
+    /// that only show up in the paths of files are created as well. Version 2 indices
+    /// only know paths after [`MPKFileReader::resolve_names`], their folder records
+    /// carry no path and are left out.
+    pub fn folder_tree(&self) -> MPKFolder {
+        let mut root = MPKFolder::default();
+        for entry in self.entries() {
+            let name = entry.name();
+            if entry.is_folder() {
+                if entry.version() == 1 {
+                    root.folder_mut(&name).index.get_or_insert(entry.index());
+                }
+                continue;
+            }
+            let parent = match name.rfind(['/', '\\']) {
+                Some(end) => &name[..end],
+                None => "",
+            };
+            root.folder_mut(parent).files.push(entry.index());
+        }
+        root
+    }
+}
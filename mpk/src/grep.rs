@@ -0,0 +1,208 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use regex::bytes::Regex;
+use serde::Serialize;
+
+use crate::{ExtractFailure, ExtractFilter, MPKFileReader, MappedShards};
+
+/// What [`MPKFileReader::grep`] searches the decoded entries for
+#[derive(Debug, Default, Clone)]
+pub struct GrepQuery {
+    /// Description shown for hits and the pattern itself
+    patterns: Vec<(String, Regex)>,
+}
+
+impl GrepQuery {
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Matches exactly these bytes, e.g. a binary UUID
+    pub fn add_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        if bytes.is_empty() {
+            anyhow::bail!("can not search for an empty byte string");
+        }
+        let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.patterns
+            .push((format!("bytes {}", hex), Regex::new(&byte_pattern(bytes))?));
+        Ok(())
+    }
+
+    /// Matches `text` encoded as UTF-8, UTF-16LE and UTF-16BE
+    pub fn add_string(&mut self, text: &str) -> anyhow::Result<()> {
+        if text.is_empty() {
+            anyhow::bail!("can not search for an empty string");
+        }
+        let utf16: Vec<u16> = text.encode_utf16().collect();
+        for (encoding, bytes) in [
+            ("UTF-8", text.as_bytes().to_vec()),
+            (
+                "UTF-16LE",
+                utf16.iter().flat_map(|unit| unit.to_le_bytes()).collect(),
+            ),
+            (
+                "UTF-16BE",
+                utf16.iter().flat_map(|unit| unit.to_be_bytes()).collect(),
+            ),
+        ] {
+            self.patterns.push((
+                format!("{:?} ({})", text, encoding),
+                Regex::new(&byte_pattern(&bytes))?,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Matches a regex against the raw bytes, see [`regex::bytes`]. Use `(?-u)` to match
+    /// bytes that aren't valid UTF-8.
+    pub fn add_regex(&mut self, pattern: &str) -> anyhow::Result<()> {
+        self.patterns
+            .push((format!("/{}/", pattern), Regex::new(pattern)?));
+        Ok(())
+    }
+
+    /// Every non empty match in `data` as pattern description, offset and size
+    fn find<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = (&'a str, usize, usize)> + 'a {
+        self.patterns.iter().flat_map(move |(description, regex)| {
+            regex
+                .find_iter(data)
+                .filter(|found| found.start() < found.end())
+                .map(move |found| {
+                    (
+                        description.as_str(),
+                        found.start(),
+                        found.end() - found.start(),
+                    )
+                })
+        })
+    }
+}
+
+/// Regex matching exactly `bytes`, whether they are valid UTF-8 or not
+fn byte_pattern(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::from("(?-u)"), |mut pattern, byte| {
+            let _ = write!(pattern, "\\x{:02x}", byte);
+            pattern
+        })
+}
+
+/// A match found by [`MPKFileReader::grep`]
+#[derive(Debug, Clone, Serialize)]
+pub struct MPKGrepHit {
+    pub index: usize,
+    /// Name the entry is extracted to
+    pub name: String,
+    /// Which pattern matched
+    pub pattern: String,
+    /// Offset into the decoded entry
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// What [`MPKFileReader::grep`] found
+#[derive(Debug, Default)]
+pub struct MPKGrepReport {
+    /// Hits sorted by entry index and offset
+    pub hits: Vec<MPKGrepHit>,
+    /// Number of entries that were decoded and searched
+    pub searched: usize,
+    pub failures: Vec<ExtractFailure>,
+}
+
+impl MPKFileReader {
+    /// Decodes the entries selected by `filter` on a thread pool and searches them for
+    /// `query`, nothing is written to disk.
+    ///
+    /// Containers are undone the same way [`MPKFileReader::extract_files_with`] does,
+    /// entries failing to decode are reported instead of aborting the search.
+    pub fn grep(&self, query: &GrepQuery, filter: &ExtractFilter) -> anyhow::Result<MPKGrepReport> {
+        let selected: Vec<usize> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(index, file)| !file.is_folder() && filter.matches_index(*index, file))
+            .map(|(index, _)| index)
+            .collect();
+        let shards = MappedShards::open(
+            self,
+            selected
+                .iter()
+                .map(|index| self.files[*index].file_number())
+                .collect::<BTreeSet<_>>(),
+        )?;
+
+        Ok(grep_parallel(
+            &selected,
+            query,
+            filter,
+            |index| {
+                let file = &self.files[index];
+                let name = self.entry_name(file);
+                let (data, alt_file_name) =
+                    self.decode_entry(file, shards.payload(file, &name)?)?;
+                Ok((alt_file_name.unwrap_or(name), data))
+            },
+            |index| self.entry_name(&self.files[index]),
+        ))
+    }
+}
+
+/// Decodes the `selected` entries on a thread pool and searches every one `filter`
+/// still matches after decoding
+pub(crate) fn grep_parallel<'a>(
+    selected: &[usize],
+    query: &GrepQuery,
+    filter: &ExtractFilter,
+    decode_entry: impl Fn(usize) -> anyhow::Result<(String, Cow<'a, [u8]>)> + Sync,
+    entry_name: impl Fn(usize) -> String + Sync,
+) -> MPKGrepReport {
+    let bar = ProgressBar::new(selected.len() as u64);
+    let results: Vec<_> = selected
+        .par_iter()
+        .map(|&index| {
+            let result = decode_entry(index).map(|(name, data)| {
+                if !filter.matches_name(&name) || !filter.matches_content(&data) {
+                    return None;
+                }
+                let mut hits: Vec<MPKGrepHit> = query
+                    .find(&data)
+                    .map(|(pattern, offset, size)| MPKGrepHit {
+                        index,
+                        name: name.clone(),
+                        pattern: pattern.to_string(),
+                        offset,
+                        size,
+                    })
+                    .collect();
+                hits.sort_by_key(|hit| hit.offset);
+                Some(hits)
+            });
+            bar.inc(1);
+            (index, result)
+        })
+        .collect();
+    bar.finish_and_clear();
+
+    let mut report = MPKGrepReport::default();
+    for (index, result) in results {
+        match result {
+            Ok(None) => {}
+            Ok(Some(hits)) => {
+                report.searched += 1;
+                report.hits.extend(hits);
+            }
+            Err(error) => report.failures.push(ExtractFailure {
+                index,
+                name: entry_name(index),
+                error,
+            }),
+        }
+    }
+    report
+}
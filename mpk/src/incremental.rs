@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+
+use crate::sink::checked_output_path;
+use crate::{ExtractFilter, ExtractFormat, ExtractManifest, ExtractOptions, ManifestEntry};
+
+/// What an earlier extraction into the same directory left behind, loaded for
+/// [`ExtractOptions::incremental`]
+pub(crate) struct IncrementalState {
+    out_dir: PathBuf,
+    manifest_path: PathBuf,
+    /// Records of the earlier run by entry name
+    previous: HashMap<String, Vec<ManifestEntry>>,
+}
+
+impl IncrementalState {
+    /// Loads the manifest of the last extraction into `out_dir`, `None` unless
+    /// `options.incremental` is set. A missing manifest means nothing was extracted yet.
+    pub(crate) fn load(out_dir: &Path, options: &ExtractOptions) -> anyhow::Result<Option<Self>> {
+        if !options.incremental {
+            return Ok(None);
+        }
+        if options.format != ExtractFormat::Directory {
+            anyhow::bail!("incremental extraction only works when extracting to a directory");
+        }
+
+        let manifest_path = options
+            .manifest
+            .clone()
+            .unwrap_or_else(|| ExtractManifest::default_path(out_dir));
+        let mut previous: HashMap<String, Vec<ManifestEntry>> = HashMap::new();
+        if manifest_path.exists() {
+            for record in ExtractManifest::read(&manifest_path)?.entries {
+                previous
+                    .entry(record.entry_name.clone())
+                    .or_default()
+                    .push(record);
+            }
+        }
+        Ok(Some(Self {
+            out_dir: out_dir.to_path_buf(),
+            manifest_path,
+            previous,
+        }))
+    }
+
+    pub(crate) fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// The record of the earlier run if it extracted the same `stored` payload for
+    /// `entry_name` and its output is still untouched, so the entry needn't be decoded
+    pub(crate) fn unchanged(
+        &self,
+        entry_name: &str,
+        stored: &[u8],
+        filter: &ExtractFilter,
+    ) -> Option<ManifestEntry> {
+        let records = self.previous.get(entry_name)?;
+        let stored_digest = format!("{:x}", Sha1::digest(stored));
+        records
+            .iter()
+            .filter(|record| !record.is_folder)
+            .filter(|record| record.stored_digest.as_ref() == Some(&stored_digest))
+            .find(|record| {
+                let Some(data) = self.read_output(&record.path, record.decoded_size) else {
+                    return false;
+                };
+                record.digest.as_ref() == Some(&format!("{:x}", Sha1::digest(&data)))
+                    && filter.matches_name(&record.path)
+                    && filter.matches_content(&data)
+            })
+            .cloned()
+    }
+
+    /// Whether the output at `path` already holds `decoded`
+    pub(crate) fn is_written(&self, path: &str, decoded: &[u8]) -> bool {
+        self.read_output(path, Some(decoded.len()))
+            .is_some_and(|data| data == decoded)
+    }
+
+    /// Reads an output, as long as it has the expected size
+    fn read_output(&self, path: &str, size: Option<usize>) -> Option<Vec<u8>> {
+        let path = self.out_dir.join(checked_output_path(path).ok()?);
+        let metadata = std::fs::metadata(&path).ok()?;
+        if !metadata.is_file() || Some(metadata.len()) != size.map(|size| size as u64) {
+            return None;
+        }
+        std::fs::read(path).ok()
+    }
+
+    /// Keeps the records of entries this run didn't touch, because they weren't
+    /// selected or failed. Outputs of entries that are gone from the archive are
+    /// deleted with `prune`, otherwise their records are kept for a later run.
+    ///
+    /// `entry_names` maps the names in the archive to their index. Returns the number
+    /// of deleted files.
+    pub(crate) fn finish(
+        &self,
+        manifest: &mut ExtractManifest,
+        entry_names: &HashMap<String, usize>,
+        prune: bool,
+    ) -> anyhow::Result<usize> {
+        let touched: HashSet<String> = manifest
+            .entries
+            .iter()
+            .map(|record| record.entry_name.clone())
+            .collect();
+        let mut gone = Vec::new();
+        for (name, records) in &self.previous {
+            if touched.contains(name) {
+                continue;
+            }
+            match entry_names.get(name) {
+                Some(index) => manifest.entries.extend(records.iter().map(|record| {
+                    let mut record = record.clone();
+                    record.index = *index;
+                    record
+                })),
+                None => gone.extend(records.iter().cloned()),
+            }
+        }
+        manifest.entries.sort_by_key(|record| record.index);
+
+        if !prune {
+            manifest.entries.extend(gone);
+            return Ok(0);
+        }
+        let paths: HashSet<&str> = manifest
+            .entries
+            .iter()
+            .map(|record| record.path.as_str())
+            .collect();
+        let mut removed = 0;
+        for record in gone {
+            if record.is_folder || paths.contains(record.path.as_str()) {
+                continue;
+            }
+            let path = self.out_dir.join(checked_output_path(&record.path)?);
+            match std::fs::remove_file(path) {
+                Ok(()) => removed += 1,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{MPKError, MPKFileReader, Shard};
+
+/// Gaps are read in chunks of this size, they can be as large as the shard
+const GAP_CHUNK_SIZE: u64 = 1 << 20;
+
+/// Range of a shard no entry refers to
+#[derive(Debug, Clone, Serialize)]
+pub struct MPKGap {
+    pub shard: u32,
+    pub offset: u64,
+    pub size: u64,
+    /// Only zero bytes, most likely padding rather than stale data
+    pub zeroed: bool,
+}
+
+/// Problem found by [`MPKFileReader::check_integrity`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MPKIntegrityIssue {
+    /// Two entries share bytes, `identical` if they have the same offset and size
+    Overlap {
+        shard: u32,
+        first: usize,
+        second: usize,
+        offset: u64,
+        size: u64,
+        identical: bool,
+    },
+    /// An entry ends behind the end of its shard
+    PastEnd {
+        index: usize,
+        shard: u32,
+        end: u64,
+        shard_size: u64,
+    },
+    /// Entries refer to a shard that doesn't exist
+    MissingShard { shard: u32, entries: usize },
+    /// The shard is compressed inside its package and can't be checked
+    CompressedShard { shard: u32, entries: usize },
+}
+
+/// Size and usage of a single shard
+#[derive(Debug, Clone, Serialize)]
+pub struct MPKShardUsage {
+    pub shard: u32,
+    /// `None` if the shard is missing or compressed
+    pub size: Option<u64>,
+    pub entries: usize,
+    /// Bytes covered by at least one entry
+    pub referenced: u64,
+}
+
+/// Result of [`MPKFileReader::check_integrity`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MPKIntegrityReport {
+    pub shards: Vec<MPKShardUsage>,
+    pub issues: Vec<MPKIntegrityIssue>,
+    pub gaps: Vec<MPKGap>,
+}
+
+impl MPKIntegrityReport {
+    /// No overlaps, out of bounds entries, missing or unreadable shards, gaps are fine
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl MPKFileReader {
+    /// Checks how the entries are laid out in their shards, without decoding anything.
+    ///
+    /// Reports entries sharing bytes, entries running past the end of their shard and
+    /// shards that don't exist or are compressed inside their package. Ranges of a shard no entry covers are collected as gaps,
+    /// patched archives tend to leave older versions of entries there.
+    pub fn check_integrity(&self) -> anyhow::Result<MPKIntegrityReport> {
+        let mut by_shard: BTreeMap<u32, Vec<(usize, u64, u64)>> = BTreeMap::new();
+        for (index, file) in self.files.iter().enumerate() {
+            if file.is_folder() {
+                continue;
+            }
+            let offset = file.offset() as u64;
+            by_shard.entry(file.file_number()).or_default().push((
+                index,
+                offset,
+                offset + file.size() as u64,
+            ));
+        }
+
+        let mut report = MPKIntegrityReport::default();
+        for (shard, mut entries) in by_shard {
+            let mut mpk_file = match self.open_shard(shard) {
+                Ok(mpk_file) => mpk_file,
+                Err(err @ (MPKError::MissingShard(_) | MPKError::CompressedShard(_))) => {
+                    let entries = entries.len();
+                    report.issues.push(match err {
+                        MPKError::MissingShard(_) => {
+                            MPKIntegrityIssue::MissingShard { shard, entries }
+                        }
+                        _ => MPKIntegrityIssue::CompressedShard { shard, entries },
+                    });
+                    report.shards.push(MPKShardUsage {
+                        shard,
+                        size: None,
+                        entries,
+                        referenced: 0,
+                    });
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let shard_size = mpk_file.size();
+
+            entries.sort_by_key(|(index, start, end)| (*start, *end, *index));
+
+            let mut referenced = 0;
+            let mut gap_start = 0;
+            // Entries still covering the start of the current one
+            let mut open: Vec<(usize, u64, u64)> = Vec::new();
+            for &(index, start, end) in &entries {
+                if end > shard_size {
+                    report.issues.push(MPKIntegrityIssue::PastEnd {
+                        index,
+                        shard,
+                        end,
+                        shard_size,
+                    });
+                }
+
+                open.retain(|(_, _, open_end)| *open_end > start);
+                if start < end {
+                    for &(previous, previous_start, previous_end) in &open {
+                        let (first, second) = (previous.min(index), previous.max(index));
+                        report.issues.push(MPKIntegrityIssue::Overlap {
+                            shard,
+                            first,
+                            second,
+                            offset: start,
+                            size: previous_end.min(end) - start,
+                            identical: previous_start == start && previous_end == end,
+                        });
+                    }
+                    open.push((index, start, end));
+                }
+
+                let (covered_start, covered_end) = (start.min(shard_size), end.min(shard_size));
+                if covered_start > gap_start {
+                    report
+                        .gaps
+                        .push(Self::gap(&mut mpk_file, shard, gap_start, covered_start)?);
+                }
+                referenced += covered_end.saturating_sub(covered_start.max(gap_start));
+                gap_start = gap_start.max(covered_end);
+            }
+            if shard_size > gap_start {
+                report
+                    .gaps
+                    .push(Self::gap(&mut mpk_file, shard, gap_start, shard_size)?);
+            }
+
+            report.shards.push(MPKShardUsage {
+                shard,
+                size: Some(shard_size),
+                entries: entries.len(),
+                referenced,
+            });
+        }
+
+        Ok(report)
+    }
+
+    /// Writes the bytes of a gap found by [`MPKFileReader::check_integrity`] to `writer`
+    pub fn write_gap<W: Write>(&self, gap: &MPKGap, writer: &mut W) -> Result<(), MPKError> {
+        let mut mpk_file = self.open_shard(gap.shard)?;
+        for_each_chunk(&mut mpk_file, gap.offset, gap.offset + gap.size, |chunk| {
+            writer.write_all(chunk)?;
+            Ok(true)
+        })
+    }
+
+    fn gap(mpk_file: &mut Shard, shard: u32, start: u64, end: u64) -> Result<MPKGap, MPKError> {
+        let mut zeroed = true;
+        for_each_chunk(mpk_file, start, end, |chunk| {
+            zeroed = chunk.iter().all(|byte| *byte == 0);
+            Ok(zeroed)
+        })?;
+        Ok(MPKGap {
+            shard,
+            offset: start,
+            size: end - start,
+            zeroed,
+        })
+    }
+}
+
+/// Reads `start..end` of a shard in chunks of at most [`GAP_CHUNK_SIZE`] and passes them
+/// to `f` until it returns `false`
+fn for_each_chunk(
+    mpk_file: &mut Shard,
+    start: u64,
+    end: u64,
+    mut f: impl FnMut(&[u8]) -> Result<bool, MPKError>,
+) -> Result<(), MPKError> {
+    let mut offset = start;
+    while offset < end {
+        let size = (end - offset).min(GAP_CHUNK_SIZE);
+        let chunk = mpk_file
+            .read_at(offset, size)?
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+        if !f(&chunk)? {
+            break;
+        }
+        offset += size;
+    }
+    Ok(())
+}
@@ -1,14 +1,53 @@
 use anyhow::Context;
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::io::{BufReader, Read, Seek, SeekFrom, Write};
-use log::error;
+use byteorder::WriteBytesExt;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use messiah_codec::{CodecError, CodecRegistry};
 use thiserror::Error;
-use try_insert_ext::EntryInsertExt;
-use crate::helpers::PythonVersion;
-
+use source::{Shard, ShardSource};
+
+mod carve;
+mod diff;
+mod entry;
+mod extract;
+mod filter;
+mod folder;
+mod grep;
 mod helpers;
+mod incremental;
+mod integrity;
+mod list;
+mod manifest;
+mod marshal;
+mod names;
+mod npk;
+mod patch;
+mod signature;
+mod sink;
+mod source;
+mod verify;
+mod writer;
+pub use carve::*;
+pub use diff::*;
+pub use entry::*;
+pub use extract::*;
+pub use filter::*;
+pub use folder::*;
+pub use grep::*;
+pub use integrity::*;
+pub use list::*;
+pub use manifest::*;
+pub use marshal::*;
+pub use names::*;
+pub use npk::*;
+pub use patch::*;
+pub use signature::*;
+pub use sink::*;
+pub use verify::*;
+pub use writer::*;
 
 #[derive(Error, Debug)]
 pub enum MPKError {
@@ -16,17 +55,91 @@ pub enum MPKError {
     InvalidInfoHeader(),
     #[error("error reading")]
     ReadError(#[from] std::io::Error),
+    #[error("mpkinfo version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("entry name {0:?} can not be stored in this mpkinfo version")]
+    InvalidEntryName(String),
+    #[error("entry {0:?} does not fit into a single shard")]
+    EntryTooLarge(String),
+    #[error("entry {0} does not exist")]
+    EntryNotFound(String),
+    #[error("entry {0:?} is a folder")]
+    EntryIsFolder(String),
+    #[error("entry {0:?} lies outside of its shard")]
+    EntryOutOfRange(String),
+    #[error("mpkinfo index ends after {read} of {count} entries")]
+    TruncatedIndex { read: u32, count: u32 },
+    #[error("folder {0:?} has a payload of {1} bytes")]
+    FolderWithPayload(String, u32),
+    #[error("shard {} is missing", .0.display())]
+    MissingShard(std::path::PathBuf),
+    #[error("shard {} is compressed inside its package, only stored shards are read", .0.display())]
+    CompressedShard(std::path::PathBuf),
+    #[error("entry {name:?} is not a valid {container} container")]
+    InvalidContainer {
+        name: String,
+        container: &'static str,
+        #[source]
+        source: CodecError,
+    },
+    #[error("entry {name:?} is stored in the unknown container {magic:?}")]
+    UnknownContainer { name: String, magic: String },
+    #[error("marshal data is invalid: {0}")]
+    InvalidMarshal(String),
+    #[error("npk header is invalid")]
+    InvalidNPKHeader(),
+    #[error("entry {0:?} is encrypted with mode {1}, which is not supported")]
+    EncryptedEntry(String, u16),
+    #[error("entry {0:?} uses compression {1}, which is not supported")]
+    UnsupportedCompression(String, u16),
+    #[error("output path {0:?} leaves the output directory")]
+    UnsafePath(String),
 }
 
+/// Index entry of a version 1 `.mpkinfo`, which stores the full path of every entry
 #[derive(Debug)]
 pub struct MPKFileEntryV1 {
-    name: String,
+    /// Name as stored in the index, which doesn't have to be valid UTF-8
+    name: Vec<u8>,
     offset: u32,
     size: u32,
     is_folder: bool,
     file_number: u32,
 }
 
+impl MPKFileEntryV1 {
+    /// Path of the entry inside the archive, `/` separated, see [`display_name`]
+    pub fn name(&self) -> Cow<'_, str> {
+        display_name(&self.name)
+    }
+
+    /// The name bytes exactly as stored in the index
+    pub fn raw_name(&self) -> &[u8] {
+        &self.name
+    }
+}
+
+/// Printable form of an entry name stored as raw bytes.
+///
+/// Valid UTF-8 is kept as is. Otherwise every byte that isn't part of a valid character
+/// is written as `%XX` and so is `%` itself, two names that aren't valid UTF-8 never
+/// end up with the same string.
+pub fn display_name(name: &[u8]) -> Cow<'_, str> {
+    if let Ok(name) = std::str::from_utf8(name) {
+        return Cow::Borrowed(name);
+    }
+    let mut display = String::with_capacity(name.len() * 3);
+    for chunk in name.utf8_chunks() {
+        display.push_str(&chunk.valid().replace('%', "%25"));
+        for byte in chunk.invalid() {
+            display.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Cow::Owned(display)
+}
+
+/// Index entry of a version 2 `.mpkinfo`, which only stores an extension and a hash
+/// of the original path
 #[derive(Debug)]
 pub struct MPKFileEntryV2 {
     name: [u8; 3],
@@ -37,48 +150,159 @@ pub struct MPKFileEntryV2 {
     file_number: u32,
 }
 
+impl MPKFileEntryV2 {
+    /// The three extension bytes stored in place of a name
+    pub fn extension(&self) -> [u8; 3] {
+        self.name
+    }
+
+    /// Hash of the original path, see [`NameDictionary`]
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+}
+
+/// A single record of an `.mpkinfo` index, see [`MPKFileReader::entries`]
 #[derive(Debug)]
-enum MPKFileEntry {
+pub enum MPKFileEntry {
     V1(MPKFileEntryV1),
     V2(MPKFileEntryV2),
 }
 
 impl MPKFileEntry {
-    fn is_folder(&self) -> bool {
+    /// Version of the index the entry comes from
+    pub fn version(&self) -> u32 {
+        match self {
+            MPKFileEntry::V1(_) => 1,
+            MPKFileEntry::V2(_) => 2,
+        }
+    }
+
+    pub fn is_folder(&self) -> bool {
         match self {
             MPKFileEntry::V1(file) => file.is_folder,
             MPKFileEntry::V2(file) => file.flags & 1 == 1,
         }
     }
 
-    fn file_number(&self) -> u32 {
+    /// Shard the payload is stored in, `0` is `basename.mpk`
+    pub fn file_number(&self) -> u32 {
         match self {
             MPKFileEntry::V1(file) => file.file_number,
             MPKFileEntry::V2(file) => file.file_number,
         }
     }
 
-    fn size(&self) -> u32 {
+    /// Size of the payload inside the shard
+    pub fn size(&self) -> u32 {
         match self {
             MPKFileEntry::V1(file) => file.size,
             MPKFileEntry::V2(file) => file.size,
         }
     }
 
-    fn offset(&self) -> u32 {
+    pub fn offset(&self) -> u32 {
         match self {
             MPKFileEntry::V1(file) => file.offset,
             MPKFileEntry::V2(file) => file.offset,
         }
     }
 
-    fn name(&self) -> String {
+    /// Flags word as stored in the index, the shard number shifted left by one and the
+    /// folder bit
+    pub fn flags(&self) -> u32 {
+        match self {
+            MPKFileEntry::V1(file) => file.file_number << 1 | file.is_folder as u32,
+            MPKFileEntry::V2(file) => file.flags,
+        }
+    }
+
+    /// Name hash, only stored by version 2 indices
+    pub fn hash(&self) -> Option<u32> {
+        match self {
+            MPKFileEntry::V1(_) => None,
+            MPKFileEntry::V2(file) => Some(file.hash),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), MPKError> {
         match self {
-            MPKFileEntry::V1(file) => file.name.to_string(),
+            MPKFileEntry::V1(file) => {
+                let name_length = u16::try_from(file.name.len())
+                    .map_err(|_| MPKError::InvalidEntryName(file.name().into_owned()))?;
+                writer.write_u16::<LittleEndian>(name_length)?;
+                writer.write_all(&file.name)?;
+                writer.write_u32::<LittleEndian>(file.offset)?;
+                writer.write_u32::<LittleEndian>(file.size)?;
+                writer.write_u32::<LittleEndian>(self.flags())?;
+            }
+            MPKFileEntry::V2(file) => {
+                writer.write_u32::<LittleEndian>(file.size)?;
+                writer.write_u32::<LittleEndian>(file.flags)?;
+                writer.write_u8(0)?;
+                writer.write_all(&file.name)?;
+                writer.write_u32::<LittleEndian>(file.hash)?;
+                writer.write_u32::<LittleEndian>(file.offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a single index entry of a version 1 or version 2 `.mpkinfo`
+    fn read_from<R: Read>(reader: &mut R, version: u32) -> Result<Self, MPKError> {
+        let file = match version {
+            1 => {
+                let name_length = reader.read_u16::<LittleEndian>()?;
+                let mut name_buffer = vec![0; name_length as usize];
+                reader.read_exact(&mut name_buffer)?;
+
+                let offset = reader.read_u32::<LittleEndian>()?;
+                let size = reader.read_u32::<LittleEndian>()?;
+                let flags = reader.read_u32::<LittleEndian>()?;
+
+                MPKFileEntry::V1(MPKFileEntryV1 {
+                    name: name_buffer,
+                    offset,
+                    size,
+                    is_folder: flags & 1 == 1,
+                    file_number: flags >> 1,
+                })
+            }
+            2 => {
+                let size = reader.read_u32::<LittleEndian>()?;
+                let flags = reader.read_u32::<LittleEndian>()?;
+                let _t = reader.read_u8()?;
+                let mut type_buffer: [u8; 3] = [0; 3];
+                reader.read_exact(&mut type_buffer)?;
+                let hash = reader.read_u32::<LittleEndian>()?;
+                let offset = reader.read_u32::<LittleEndian>()?;
+
+                MPKFileEntry::V2(MPKFileEntryV2 {
+                    name: type_buffer,
+                    offset,
+                    size,
+                    flags,
+                    hash,
+                    file_number: flags >> 1,
+                })
+            }
+            _ => return Err(MPKError::UnsupportedVersion(version)),
+        };
+
+        if file.is_folder() && file.size() != 0 {
+            return Err(MPKError::FolderWithPayload(file.name(), file.size()));
+        }
+        Ok(file)
+    }
+
+    /// Name in the index, version 2 entries are named `file_{number}_{hash}.{ext}`
+    pub fn name(&self) -> String {
+        match self {
+            MPKFileEntry::V1(file) => file.name().into_owned(),
             MPKFileEntry::V2(file) => if self.is_folder() {
-                format!("{}", std::str::from_utf8(&file.name).unwrap().replace("/", "_"))
+                String::from_utf8_lossy(&file.name).replace("/", "_")
             } else {
-                format!("file_{}_{}.{}", file.file_number, file.hash, std::str::from_utf8(&file.name).unwrap())
+                format!("file_{}_{}.{}", file.file_number, file.hash, String::from_utf8_lossy(&file.name))
             }
         }
     }
@@ -91,288 +315,327 @@ pub struct MPKFileHeader {
 }
 
 impl MPKFileHeader {
-    fn read_header<T>(reader: &mut std::io::BufReader<T>) -> Result<MPKFileHeader, MPKError>
-    where
-        T: std::io::Read,
-        T: std::io::Seek,
-    {
-        let version = reader.read_u32::<LittleEndian>()?;
-        let file_count = reader.read_u32::<LittleEndian>()?;
+    fn read_header<R: Read>(reader: &mut R) -> Result<MPKFileHeader, MPKError> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => MPKError::InvalidInfoHeader(),
+            _ => err.into(),
+        })?;
+        let version = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let file_count = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
         Ok(Self {
             version,
             file_count,
         })
     }
+
+    fn write_header<W: Write>(&self, writer: &mut W) -> Result<(), MPKError> {
+        writer.write_u32::<LittleEndian>(self.version)?;
+        writer.write_u32::<LittleEndian>(self.file_count)?;
+        Ok(())
+    }
+}
+
+/// Path of the shard with the given `file_number` that belongs to the `.mpkinfo` at `info_path`.
+///
+/// Shard 0 is `basename.mpk`, every following shard is `basename{n}.mpk`.
+pub(crate) fn shard_path(info_path: &std::path::Path, file_number: u32) -> std::path::PathBuf {
+    let basename = info_path.file_stem().unwrap_or_default().to_string_lossy();
+    let mpk_file = if file_number == 0 {
+        format!("{}.mpk", basename)
+    } else {
+        format!("{}{}.mpk", basename, file_number)
+    };
+    info_path.with_file_name(mpk_file)
 }
 
 #[derive(Debug)]
 pub struct MPKFileReader {
     path: std::path::PathBuf,
-    _file: std::fs::File,
+    codecs: CodecRegistry,
+    signatures: SignatureRegistry,
+    source: ShardSource,
+    /// Original paths of version 2 entries by name hash
+    names: HashMap<u32, String>,
     _header: MPKFileHeader,
     files: Vec<MPKFileEntry>,
 }
 
 impl MPKFileReader {
+    /// Opens the `.mpkinfo` at `path`. The path may run through a zip package like an
+    /// APK or OBB, e.g. `game.apk/assets/res.mpkinfo`, see [`MPKFileReader::from_package`]
     pub fn new<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
-        let file = std::fs::File::open(&path).with_context(|| {
+        let path = path.as_ref();
+        if !path.exists() {
+            if let Some((package, member)) = source::split_package_path(path) {
+                return Self::from_package(package, &member);
+            }
+        }
+        let file = std::fs::File::open(path).with_context(|| {
             format!(
                 "Failed to read .mpkinfo file from {}",
-                path.as_ref().to_string_lossy()
+                path.to_string_lossy()
             )
         })?;
-        let mut reader = BufReader::new(&file);
-        let header = MPKFileHeader::read_header(&mut reader)?;
+        let (header, files) = Self::read_index(&mut BufReader::new(&file))?;
+        Ok(Self::from_entries(path, header, files))
+    }
 
-        let mut files = Vec::new();
-        for _ in 0..header.file_count {
-            // Read indices
-            match header.version {
-                1 => {
-                    let name_length = reader.read_u16::<LittleEndian>()?;
-                    let mut name_buffer = vec![0; name_length as usize];
-                    reader.read_exact(&mut name_buffer)?;
-
-                    let offset = reader.read_u32::<LittleEndian>()?;
-                    let size = reader.read_u32::<LittleEndian>()?;
-                    let flags = reader.read_u32::<LittleEndian>()?;
-
-                    let is_folder = flags & 1 == 1;
-                    let file_number = flags >> 1;
-
-                    if is_folder {
-                        assert!(size == 0);
-                    }
+    /// Opens the `.mpkinfo` stored as `info_member` in the zip package at `package`.
+    ///
+    /// The shards are looked up next to it inside the package and read in place, so
+    /// they have to be stored without compression, which APKs and OBBs do for them.
+    pub fn from_package<P: AsRef<std::path::Path>>(
+        package: P,
+        info_member: &str,
+    ) -> anyhow::Result<Self> {
+        let package = package.as_ref();
+        let mut archive = source::open_package(package)?;
+        let (header, files) = {
+            let info = archive.by_name(info_member).with_context(|| {
+                format!(
+                    "Failed to read .mpkinfo file {} from {}",
+                    info_member,
+                    package.to_string_lossy()
+                )
+            })?;
+            Self::read_index(&mut BufReader::new(info))?
+        };
+        let file_numbers: std::collections::BTreeSet<u32> = files
+            .iter()
+            .filter(|file| !file.is_folder())
+            .map(|file| file.file_number())
+            .collect();
+        let source = ShardSource::package(package, &mut archive, info_member, file_numbers)?;
+
+        let mut reader = Self::from_entries(&package.join(info_member), header, files);
+        reader.source = source;
+        Ok(reader)
+    }
 
-                    files.push(MPKFileEntry::V1(MPKFileEntryV1 {
-                        name: String::from_utf8(name_buffer)?,
-                        offset,
-                        size,
-                        is_folder,
-                        file_number,
-                    }))
-                }
-                2 => {
-                    let size = reader.read_u32::<LittleEndian>()?;
-                    let flags = reader.read_u32::<LittleEndian>()?;
-                    let t = reader.read_u8()?;
-                    let mut type_buffer: [u8; 3] = [0; 3];
-                    reader.read_exact(&mut type_buffer)?;
-                    let hash = reader.read_u32::<LittleEndian>()?;
-                    let offset = reader.read_u32::<LittleEndian>()?;
-                    let file_number = flags >> 1;
-
-                    if flags & 1 == 1 {
-                        assert!(size == 0);
-                    }
+    fn read_index<R: Read>(reader: &mut R) -> Result<(MPKFileHeader, Vec<MPKFileEntry>), MPKError> {
+        let header = MPKFileHeader::read_header(reader)?;
+
+        if header.version != 1 && header.version != 2 {
+            return Err(MPKError::UnsupportedVersion(header.version));
+        }
 
-                    files.push(MPKFileEntry::V2(MPKFileEntryV2 {
-                        name: type_buffer,
-                        offset,
-                        size,
-                        flags,
-                        hash,
-                        file_number
-                    }))
+        let mut files = Vec::new();
+        for index in 0..header.file_count {
+            let file = MPKFileEntry::read_from(reader, header.version).map_err(|err| {
+                match err {
+                    MPKError::ReadError(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                        MPKError::TruncatedIndex {
+                            read: index,
+                            count: header.file_count,
+                        }
+                    }
+                    err => err,
                 }
-                _ => return Err(MPKError::InvalidInfoHeader().into()),
-            }
+            })?;
+            files.push(file);
         }
+        Ok((header, files))
+    }
 
-        Ok(MPKFileReader {
-            path: path.as_ref().as_os_str().to_os_string().into(),
-            _file: file,
+    /// Reader for entries that don't come from an `.mpkinfo` on disk, `path` only
+    /// decides where the shards are looked up
+    pub(crate) fn from_entries(
+        path: &std::path::Path,
+        header: MPKFileHeader,
+        files: Vec<MPKFileEntry>,
+    ) -> Self {
+        MPKFileReader {
+            path: path.to_path_buf(),
+            codecs: CodecRegistry::default(),
+            signatures: SignatureRegistry::default(),
+            source: ShardSource::Directory,
+            names: HashMap::new(),
             _header: header,
             files,
-        })
+        }
     }
 
-    pub fn extract_files<P: AsRef<std::path::Path>>(&self, out_dir: P) -> anyhow::Result<()> {
-        use indicatif::ProgressBar;
-
-        let bar = ProgressBar::new(self.files.len() as u64);
-
-        std::fs::create_dir_all(&out_dir)?;
-
-        let basename = self.path.file_stem().unwrap().to_str().unwrap();
-        let parent_path = self.path.parent().unwrap();
-
-        let mut mpk_map = HashMap::new();
-        for file in &self.files {
-            if !file.is_folder() {
-                let file_number = file.file_number();
-                let mpk_file = mpk_map.entry(file_number).or_try_insert_with(|| {
-                    let mpk_file = if file_number == 0 {
-                        format!("{}.mpk", basename)
-                    } else {
-                        format!("{}{}.mpk", basename, file_number)
-                    };
-                    std::fs::File::open(parent_path.join(mpk_file))
-                })?;
-
-                let offset = mpk_file.seek(SeekFrom::Start(file.offset().into()))?;
-                assert_eq!(offset, file.offset().into());
-                let mut file_buffer = vec![0; file.size() as usize];
-                mpk_file.read_exact(&mut file_buffer)?;
-
-                let mut reader = std::io::Cursor::new(&file_buffer);
-                let mut magic = vec![0; 4];
-                let _ = reader.read_exact(&mut magic);
-
-                // TODO(alexander): This _should_ probably be optional
-                let (file_buffer, alt_file_name): (_, Option<String>) = if magic == b"ZZZ4" {
-                    (
-                        lz4_flex::decompress_size_prepended(&file_buffer[4..])?,
-                        None,
-                    )
-                } else if magic == b"CCCC" {
-                    let mut magic = vec![0; 4];
-                    let _ = reader.read_exact(&mut magic);
-                    if magic == b"ZZZ4" {
-                        let uncompressed_size = reader.read_i32::<LittleEndian>()?;
-                        let mut buffer = vec![0; 0_usize];
-                        reader.read_to_end(&mut buffer)?;
-                        // There is an unknown "overhang" of 20 bytes at the end, no idea what it is
-                        // Ignore for now
-                        // _could_ be a sha1 actually
-                        (
-                            lz4_flex::decompress(
-                                &buffer[..buffer.len() - 20],
-                                uncompressed_size as usize,
-                            )?,
-                            None,
-                        )
-                    } else if magic == b"LZMA" {
-                        let uncompressed_size = reader.read_i32::<LittleEndian>()?;
-                        let mut buffer = vec![0; 0_usize];
-                        reader.read_to_end(&mut buffer)?;
-                        let mut decompressed = vec![];
-                        lzma_rs::lzma_decompress_with_options(
-                            &mut std::io::Cursor::new(&buffer),
-                            &mut decompressed,
-                            &lzma_rs::decompress::Options {
-                                unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(
-                                    Some(uncompressed_size as u64),
-                                ),
-                                memlimit: None,
-                                allow_incomplete: false,
-                            },
-                        )?;
-                        let file_name = Self::detect_file_name_with_extension(file, &decompressed);
-                        (decompressed, Some(file_name))
-                    } else {
-                        // let file_name = Self::detect_file_name_with_extension(file, &file_buffer);
-                        (file_buffer, None)
-                    }
-                } else if &magic[..2] == b"\xE2\x06" {
-                    // This is a mangled zlib compressed file
-                    // TODO(alexander): Move handling of these to a new crate
-                    // TODO(alexander): Reduce number of vec allocations
-                    reader.seek(SeekFrom::Start(0))?;
-                    let mut buffer = vec![0; 0_usize];
-                    reader.read_to_end(&mut buffer)?;
-
-                    let offset = (buffer.len() - 8) % 37;
-                    let end = 128 - offset;
-                    let end = end.min(buffer.len());
-                    // eprintln!("{} {} {}", buffer.len(), offset, end);
-                    let head = &mut buffer[..end];
-                    for x in head.iter_mut() {
-                        *x ^= 154;
-                    }
-                    let end = if end == buffer.len() {
-                        end
-                    } else {
-                        buffer.len() - 8
-                    };
-                    use compress::zlib;
-                    let mut decoder = zlib::Decoder::new(&buffer[..end]);
-                    let mut result_buffer = vec![];
-                    decoder.read_to_end(&mut result_buffer)?;
-
-                    if let Ok(file_name) = helpers::file_name_from_py_buffer(&result_buffer) {
-                        if file_name.is_empty() {
-                            let file_name = Self::detect_file_name_with_extension(file, &result_buffer);
-                            (result_buffer, Some(file_name))
-                        } else {
-                            (
-                                result_buffer,
-                                Some(format!("Script/Python/{}c", file_name)),
-                            )
-                        }
-                    } else {
-                        let file_name = Self::detect_file_name_with_extension(file, &file_buffer);
-                        (file_buffer, Some(file_name))
-                    }
-                } else {
-                    (file_buffer, None)
-                };
-
-                let file_name = if let Some(file_name) = alt_file_name {
-                    file_name
-                } else {
-                    file.name()
-                };
-                let out_file_path = out_dir.as_ref().join(file_name);
-                std::fs::create_dir_all(out_file_path.parent().unwrap())?;
-
-                let mut out_file = std::fs::File::create(&out_file_path)
-                    .context(out_file_path.display().to_string())?;
-                out_file.write_all(&file_buffer)?;
-            } else {
-                let out_file_path = out_dir.as_ref().join(file.name());
-                std::fs::create_dir_all(out_file_path)?;
-            }
+    /// Codecs used to decode entry payloads, register new ones here before extracting
+    pub fn codecs_mut(&mut self) -> &mut CodecRegistry {
+        &mut self.codecs
+    }
 
-            bar.inc(1);
+    /// File types recognised when naming entries whose container hides the name
+    pub fn signatures_mut(&mut self) -> &mut SignatureRegistry {
+        &mut self.signatures
+    }
+
+    /// Recovers the original paths of version 2 entries from `dictionary`, entries
+    /// without a match keep their `file_{number}_{hash}.{ext}` names
+    pub fn resolve_names(&mut self, dictionary: &NameDictionary) -> Option<NameResolution> {
+        let resolution = dictionary.resolve(self)?;
+        self.names.extend(resolution.names.clone());
+        Some(resolution)
+    }
+
+    /// Name of the entry in the index, or its recovered path for version 2 entries
+    pub(crate) fn entry_name(&self, file: &MPKFileEntry) -> String {
+        match file {
+            MPKFileEntry::V2(entry) if !file.is_folder() => match self.names.get(&entry.hash) {
+                Some(name) => name.clone(),
+                None => file.name(),
+            },
+            _ => file.name(),
         }
+    }
 
-        bar.finish();
+    /// Number of entries in the index, folders included
+    pub fn entry_count(&self) -> usize {
+        self.files.len()
+    }
 
-        Ok(())
+    /// Index names of all entries in index order
+    pub fn entry_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.files.iter().map(|file| self.entry_name(file))
     }
 
-    fn detect_file_name_with_extension(file: &MPKFileEntry, decompressed: &Vec<u8>) -> String {
-        let result = tree_magic_mini::from_u8(&decompressed);
-        let extension = match result {
-            "application/x-executable" => "exe",
-            "application/x-cpio" => "cpio",
-            "image/ktx" => "ktx",
-            "image/png" => "png",
-            "image/x-dds" => "dds",
-            "image/x-win-bitmap" => "bmp",
-            "application/xml" => "xml",
-            "text/x-matlab" => "mat", // Maybe m instead?
-            "application/x-apple-systemprofiler+xml" => "xml",
-            "text/x-modelica" => "mo",
-            "text/x-csrc" => "c",
-            "font/ttf" => "ttf",
-            "image/bmp" => "bmp",
-            "application/zip" => "zip",
-            "image/jpeg" => "jpg",
-            "image/vnd.zbrush.pcx" => "pcx",
-            "audio/mpeg" => "mp3",
-            "audio/x-wav" => "wav",
-            "audio/vnd.wave" => "wav",
-            "application/x-java-jce-keystore" => "pem",
-            "application/x-font-ttf" => "ttf",
-            "application/octet-stream" => return file.name().to_owned(),
-            "video/mp4" => "mp4",
-            "text/plain" => "txt",
-            _ => {
-                error!("Unhandled mime type {}", result);
-                "dat"
-            }
-        };
-        let name = file.name();
-        let path = std::path::Path::new(&name);
-        if path.parent().unwrap().to_string_lossy().is_empty() {
-            let file_name = format!("{}.{}", path.file_stem().unwrap().to_string_lossy(), extension);
-            file_name
-        } else {
-            let file_name = format!("{}/{}.{}", path.parent().unwrap().to_string_lossy(), path.file_stem().unwrap().to_string_lossy(), extension);
-            file_name
+    /// Every entry in index order, folders included
+    pub fn entries(&self) -> impl ExactSizeIterator<Item = MPKEntry<'_>> + '_ {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| MPKEntry::new(self, index, file))
+    }
+
+    pub fn entry(&self, index: usize) -> Option<MPKEntry<'_>> {
+        self.files
+            .get(index)
+            .map(|file| MPKEntry::new(self, index, file))
+    }
+
+    /// Looks up an entry by its index name, not by the name extraction may detect for it
+    pub fn find_entry(&self, name: &str) -> Option<usize> {
+        self.files.iter().position(|file| self.entry_name(file) == name)
+    }
+
+    /// Reads and decodes a single entry without touching any other entry of the archive
+    pub fn open_entry(&self, index: usize) -> anyhow::Result<MPKEntryReader> {
+        let file = self
+            .files
+            .get(index)
+            .ok_or_else(|| MPKError::EntryNotFound(index.to_string()))?;
+        if file.is_folder() {
+            return Err(MPKError::EntryIsFolder(self.entry_name(file)).into());
         }
 
+        let mut mpk_file = self.open_shard(file.file_number())?;
+        let file_buffer = self.read_entry(&mut mpk_file, file)?;
+        let (decoded, alt_file_name) = self.decode_entry(file, &file_buffer)?;
+        let decoded = match decoded {
+            Cow::Borrowed(_) => file_buffer,
+            Cow::Owned(decoded) => decoded,
+        };
+
+        Ok(MPKEntryReader::new(
+            alt_file_name.unwrap_or_else(|| self.entry_name(file)),
+            decoded,
+        ))
+    }
+
+    pub fn open_entry_by_name(&self, name: &str) -> anyhow::Result<MPKEntryReader> {
+        let index = self
+            .find_entry(name)
+            .ok_or_else(|| MPKError::EntryNotFound(format!("{:?}", name)))?;
+        self.open_entry(index)
+    }
+
+    /// Opens the shard with the given `file_number`
+    fn open_shard(&self, file_number: u32) -> Result<Shard, MPKError> {
+        self.source.open_shard(&self.path, file_number)
+    }
+
+    fn read_entry(&self, shard: &mut Shard, file: &MPKFileEntry) -> Result<Vec<u8>, MPKError> {
+        // The size comes straight from the index, the shard only allocates what it holds
+        shard
+            .read_at(file.offset().into(), file.size().into())?
+            .ok_or_else(|| MPKError::EntryOutOfRange(self.entry_name(file)))
+    }
+
+    /// Undoes the container an entry payload is stored in, see [`decode_payload`]
+    fn decode_entry<'a>(
+        &self,
+        file: &MPKFileEntry,
+        file_buffer: &'a [u8],
+    ) -> Result<(Cow<'a, [u8]>, Option<String>), MPKError> {
+        decode_payload(
+            &self.codecs,
+            &self.signatures,
+            &self.entry_name(file),
+            file_buffer,
+        )
+    }
+}
+
+/// Undoes the container a payload is stored in.
+///
+/// Returns the decoded data together with the name extraction should use
+/// instead of `name`, if the container allows detecting a better one.
+pub(crate) fn decode_payload<'a>(
+    codecs: &CodecRegistry,
+    signatures: &SignatureRegistry,
+    name: &str,
+    file_buffer: &'a [u8],
+) -> Result<(Cow<'a, [u8]>, Option<String>), MPKError> {
+    // TODO(alexander): This _should_ probably be optional
+    let Some(codec) = codecs.detect(file_buffer) else {
+        return Ok((Cow::Borrowed(file_buffer), None));
+    };
+    let file_buffer = codec
+        .decode_limited(file_buffer, codecs.max_decoded_size())
+        .map_err(|source| match source {
+            CodecError::UnknownContainer(magic) => MPKError::UnknownContainer {
+                name: name.to_string(),
+                magic,
+            },
+            source => MPKError::InvalidContainer {
+                name: name.to_string(),
+                container: codec.name(),
+                source,
+            },
+        })?;
+    if !codec.sniff_name() {
+        return Ok((Cow::Owned(file_buffer), None));
+    }
+
+    let file_name = sniff_file_name(signatures, name, &file_buffer);
+    Ok((Cow::Owned(file_buffer), Some(file_name)))
+}
+
+/// Name for decoded data whose container hides the original name, the script path
+/// compiled into a pyc or `name` with the extension of the detected file type
+pub(crate) fn sniff_file_name(
+    signatures: &SignatureRegistry,
+    name: &str,
+    decoded: &[u8],
+) -> String {
+    match helpers::file_name_from_py_buffer(decoded) {
+        Ok(file_name) if !file_name.is_empty() => format!("Script/Python/{}c", file_name),
+        _ => detect_file_name_with_extension(signatures, name, decoded),
+    }
+}
+
+/// Replaces the extension of `name` with the one of the detected file type, engine
+/// signatures go before the sniffed MIME type
+pub(crate) fn detect_file_name_with_extension(
+    signatures: &SignatureRegistry,
+    name: &str,
+    decompressed: &[u8],
+) -> String {
+    let Some(extension) = signatures.extension(decompressed) else {
+        return name.to_string();
+    };
+    let path = std::path::Path::new(name);
+    if path.parent().unwrap_or(path).to_string_lossy().is_empty() {
+        let file_name = format!("{}.{}", path.file_stem().unwrap_or_default().to_string_lossy(), extension);
+        file_name
+    } else {
+        let file_name = format!("{}/{}.{}", path.parent().unwrap_or(path).to_string_lossy(), path.file_stem().unwrap_or_default().to_string_lossy(), extension);
+        file_name
     }
 }
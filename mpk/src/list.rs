@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use try_insert_ext::EntryInsertExt;
+
+use crate::{MPKFileEntry, MPKFileReader, Shard};
+
+/// Everything we know about a single index entry, as shown by `messiah-mpk list`
+#[derive(Debug, Clone, Serialize)]
+pub struct MPKEntryInfo {
+    pub index: usize,
+    pub shard: u32,
+    pub offset: u32,
+    /// Size of the payload inside the shard
+    pub size: u32,
+    pub is_folder: bool,
+    /// Name hash, only stored by version 2 indices
+    pub hash: Option<u32>,
+    /// Name of the container the payload is stored in, `None` for folders
+    pub container: Option<String>,
+    /// Size of the data extraction writes, `None` for folders
+    pub decompressed_size: Option<usize>,
+    /// Name the entry is extracted to
+    pub name: String,
+    /// Why the payload couldn't be read or decoded, the entry is listed regardless
+    pub error: Option<String>,
+}
+
+impl MPKFileReader {
+    /// Collects [`MPKEntryInfo`] for every entry, this decodes every payload to find the
+    /// names and sizes extraction would produce. Entries that fail to read or decode are
+    /// listed with their error.
+    pub fn list_entries(&self) -> anyhow::Result<Vec<MPKEntryInfo>> {
+        let mut mpk_map = HashMap::new();
+        let mut entries = Vec::with_capacity(self.files.len());
+        for (index, file) in self.files.iter().enumerate() {
+            let mut container = None;
+            let (decompressed_size, name, error) = if file.is_folder() {
+                (None, self.entry_name(file), None)
+            } else {
+                match self.decode_listed_entry(&mut mpk_map, file, &mut container) {
+                    Ok((decompressed_size, name)) => (Some(decompressed_size), name, None),
+                    Err(err) => (None, self.entry_name(file), Some(format!("{:#}", err))),
+                }
+            };
+
+            entries.push(MPKEntryInfo {
+                index,
+                shard: file.file_number(),
+                offset: file.offset(),
+                size: file.size(),
+                is_folder: file.is_folder(),
+                hash: file.hash(),
+                container,
+                decompressed_size,
+                name,
+                error,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Decoded size and extraction name of `file`, `container` is set as soon as the
+    /// payload is read
+    fn decode_listed_entry(
+        &self,
+        mpk_map: &mut HashMap<u32, Shard>,
+        file: &MPKFileEntry,
+        container: &mut Option<String>,
+    ) -> anyhow::Result<(usize, String)> {
+        let file_number = file.file_number();
+        let mpk_file = mpk_map
+            .entry(file_number)
+            .or_try_insert_with(|| self.open_shard(file_number))?;
+        let file_buffer = self.read_entry(mpk_file, file)?;
+        *container = Some(self.codecs.container_name(&file_buffer).to_string());
+        let (file_buffer, alt_file_name) = self.decode_entry(file, &file_buffer)?;
+        Ok((
+            file_buffer.len(),
+            alt_file_name.unwrap_or_else(|| self.entry_name(file)),
+        ))
+    }
+}
@@ -1,18 +1,377 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use std::fmt::Debug;
+use std::io::Write;
 
-use messiah_mpk::MPKFileReader;
+use log::{error, info, warn};
+use messiah_mpk::{
+    ExtractFilter, ExtractFormat, ExtractOptions, ExtractSummary, GrepQuery, MPKCarver,
+    MPKCompression, MPKDiff, MPKEntryInfo, MPKFileReader, MPKIntegrityIssue, MPKPatcher,
+    MPKVerifyStatus, NPKFileReader, NameDictionary,
+};
+use messiah_resources::Repository;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)] // parsed once per run
+enum Command {
+    /// Extract all files of the archive
+    Extract {
+        #[clap(
+            help = "Input .mpkinfo file, we derive all the required files based on that and it's location. It may lie inside an .apk or .obb, like game.apk/assets/res.mpkinfo. NeoX .npk archives are read as well"
+        )]
+        mpkinfo_file: String,
+
+        #[clap(
+            help = "Desired output directory to extract files to, paths ending in .tar, .tar.zst or .zip are written as a single archive instead"
+        )]
+        out_dir: String,
+
+        #[clap(flatten)]
+        filter: FilterArgs,
+
+        #[clap(flatten)]
+        names: NameArgs,
+
+        #[clap(
+            help = "Number of threads decoding and writing entries, defaults to one per core",
+            short = 'j',
+            long
+        )]
+        threads: Option<usize>,
+
+        #[clap(
+            help = "Log entries that fail to extract and carry on with the rest instead of stopping",
+            long
+        )]
+        keep_going: bool,
+
+        #[clap(
+            help = "Write a JSON manifest mapping every extracted file back to its entry",
+            long
+        )]
+        manifest: Option<String>,
+
+        #[clap(
+            help = "Only extract entries whose output is missing or outdated, using the manifest of the last run (next to the output directory unless --manifest is given)",
+            long
+        )]
+        incremental: bool,
+
+        #[clap(
+            help = "With --incremental, delete outputs of entries that are gone from the archive",
+            long,
+            requires = "incremental"
+        )]
+        prune: bool,
+
+        #[clap(
+            help = "Fail entries decoding to more than this many bytes instead of allocating them, defaults to 512 MiB",
+            long
+        )]
+        max_entry_size: Option<usize>,
+
+        #[clap(
+            help = "Most decoded bytes held in memory while waiting to be written, unlimited by default",
+            long
+        )]
+        max_memory: Option<usize>,
+    },
+    /// List every entry of the archive together with the name extraction would assign
+    #[clap(alias = "info")]
+    List {
+        #[clap(
+            help = "Input .mpkinfo file, we derive all the required files based on that and it's location. It may lie inside an .apk or .obb, like game.apk/assets/res.mpkinfo. NeoX .npk archives are read as well"
+        )]
+        mpkinfo_file: String,
+
+        #[clap(help = "Output format", short, long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        #[clap(flatten)]
+        names: NameArgs,
+    },
+    /// Decode every entry and check it against the checksums its container carries
+    Verify {
+        #[clap(
+            help = "Input .mpkinfo file, we derive all the required files based on that and it's location. It may lie inside an .apk or .obb, like game.apk/assets/res.mpkinfo"
+        )]
+        mpkinfo_file: String,
+
+        #[clap(help = "Print the result for every entry as JSON", long)]
+        json: bool,
+    },
+    /// Compare two archives and report added, removed and changed entries
+    Diff {
+        #[clap(help = "The .mpkinfo file of the old archive")]
+        old_mpkinfo_file: String,
+
+        #[clap(help = "The .mpkinfo file of the new archive")]
+        new_mpkinfo_file: String,
+
+        #[clap(
+            help = "Decode every entry and compare the content instead of the stored payload",
+            long
+        )]
+        content: bool,
+
+        #[clap(help = "Print the report as JSON", long)]
+        json: bool,
+
+        #[clap(
+            help = "Extract added and changed entries of the new archive to this directory",
+            long
+        )]
+        extract_changed: Option<String>,
+    },
+    /// Check the entries for overlaps, entries past the end of their shard, missing shards and gaps
+    Scan {
+        #[clap(
+            help = "Input .mpkinfo file, we derive all the required files based on that and it's location. It may lie inside an .apk or .obb, like game.apk/assets/res.mpkinfo"
+        )]
+        mpkinfo_file: String,
+
+        #[clap(help = "Print the report as JSON", long)]
+        json: bool,
+
+        #[clap(help = "Write the contents of every gap to this directory", long)]
+        dump_gaps: Option<String>,
+
+        #[clap(help = "Also dump gaps that only contain zero bytes", long)]
+        include_zeroed: bool,
+    },
+    /// Recover entries from .mpk shards whose .mpkinfo is missing
+    Carve {
+        #[clap(help = "The first .mpk shard, following shards are picked up next to it")]
+        mpk_file: String,
+
+        #[clap(help = "Extract the recovered entries to this directory")]
+        out_dir: Option<String>,
+
+        #[clap(help = "Write a .mpkinfo for the recovered entries next to the shards", long)]
+        rebuild_index: bool,
+
+        #[clap(help = "Print the recovered entries as JSON", long)]
+        json: bool,
+
+        #[clap(
+            help = "Number of following signatures tried as the end of a container",
+            long,
+            default_value_t = 16
+        )]
+        lookahead: usize,
+    },
+    /// Search the decoded entries for strings, bytes or regexes without extracting them
+    Grep {
+        #[clap(
+            help = "Input .mpkinfo file, we derive all the required files based on that and it's location. It may lie inside an .apk or .obb, like game.apk/assets/res.mpkinfo. NeoX .npk archives are read as well"
+        )]
+        mpkinfo_file: String,
+
+        #[clap(
+            help = "Search for this string encoded as UTF-8, UTF-16LE and UTF-16BE",
+            long = "string"
+        )]
+        strings: Vec<String>,
+
+        #[clap(help = "Search for these hex encoded bytes, e.g. 'deadbeef'", long)]
+        hex: Vec<String>,
+
+        #[clap(
+            help = "Search for this regex over the raw bytes, (?-u) matches non UTF-8 data",
+            long = "pattern"
+        )]
+        patterns: Vec<String>,
+
+        #[clap(flatten)]
+        filter: FilterArgs,
+
+        #[clap(flatten)]
+        names: NameArgs,
+
+        #[clap(help = "Print the hits as JSON", long)]
+        json: bool,
+    },
+    /// Replace or add entries in place by appending them to the last shard, only the .mpkinfo is rewritten
+    Patch {
+        #[clap(help = "The .mpkinfo file of the archive, the shards have to lie next to it")]
+        mpkinfo_file: String,
+
+        #[clap(
+            help = "Directory with the files to replace or add, named by their path relative to it",
+            required_unless_present = "undo"
+        )]
+        dir: Option<String>,
+
+        #[clap(
+            help = "How to store the new payloads",
+            long,
+            value_enum,
+            default_value_t = PatchCompression::None
+        )]
+        compression: PatchCompression,
+
+        #[clap(
+            help = "Restore the .mpkinfo and its shards from before the first patch instead",
+            long,
+            conflicts_with = "dir"
+        )]
+        undo: bool,
+    },
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum PatchCompression {
+    None,
+    Lz4,
+    Zlib,
+}
+
+impl From<PatchCompression> for MPKCompression {
+    fn from(compression: PatchCompression) -> Self {
+        match compression {
+            PatchCompression::None => MPKCompression::None,
+            PatchCompression::Lz4 => MPKCompression::Lz4,
+            PatchCompression::Zlib => MPKCompression::MangledZlib,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct FilterArgs {
+    #[clap(
+        help = "Only extract entries matching this glob, e.g. 'Script/Python/**'",
+        long
+    )]
+    include: Vec<String>,
+
+    #[clap(help = "Skip entries matching this glob", long)]
+    exclude: Vec<String>,
+
+    #[clap(help = "Only extract entries whose name matches this regex", long)]
+    regex: Vec<String>,
+
     #[clap(
-        help = "Input .mpkinfo file, we derive all the required files based on that and it's location"
+        help = "Only extract entries with this (detected) extension",
+        long = "ext"
     )]
-    mpkinfo_file: String,
+    extensions: Vec<String>,
 
-    #[clap(help = "Desired output directory to extract files to")]
-    out_dir: String,
+    #[clap(
+        help = "Only extract entries whose content has this mime type",
+        long = "mime"
+    )]
+    mime_types: Vec<String>,
+
+    #[clap(
+        help = "Only extract entries stored in this shard number",
+        long = "shard"
+    )]
+    shards: Vec<u32>,
+
+    #[clap(help = "Minimum stored size in bytes", long)]
+    min_size: Option<u64>,
+
+    #[clap(help = "Maximum stored size in bytes", long)]
+    max_size: Option<u64>,
+}
+
+impl FilterArgs {
+    fn to_filter(&self) -> anyhow::Result<ExtractFilter> {
+        let mut filter = ExtractFilter::default();
+        for pattern in &self.include {
+            filter.add_include(pattern)?;
+        }
+        for pattern in &self.exclude {
+            filter.add_exclude(pattern)?;
+        }
+        for pattern in &self.regex {
+            filter.add_regex(pattern)?;
+        }
+        for extension in &self.extensions {
+            filter.add_extension(extension);
+        }
+        for mime_type in &self.mime_types {
+            filter.add_mime_type(mime_type);
+        }
+        for shard in &self.shards {
+            filter.add_shard(*shard);
+        }
+        filter.set_size_range(self.min_size, self.max_size);
+        Ok(filter)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct NameArgs {
+    #[clap(
+        help = "Word list with one path per line to recover version 2 names from",
+        long
+    )]
+    names: Vec<String>,
+
+    #[clap(
+        help = "Resource .repository file to recover version 2 names from",
+        long
+    )]
+    repository: Vec<String>,
+
+    #[clap(
+        help = "Recover version 2 names from the file names of the scripts in the archive",
+        long
+    )]
+    python_names: bool,
+}
+
+impl NameArgs {
+    fn open_reader(&self, mpkinfo_file: &str) -> anyhow::Result<MPKFileReader> {
+        let mut reader = MPKFileReader::new(mpkinfo_file)?;
+
+        let mut dictionary = NameDictionary::default();
+        for word_list in &self.names {
+            dictionary.add_word_list(word_list)?;
+        }
+        for repository in &self.repository {
+            dictionary.add_repository(&Repository::from_file(repository)?);
+        }
+        if self.python_names {
+            for failure in dictionary.add_python_names(&reader) {
+                warn!(
+                    "Skipped {} while collecting script names: {:#}",
+                    failure.name, failure.error
+                );
+            }
+        }
+        if dictionary.is_empty() {
+            return Ok(reader);
+        }
+
+        match reader.resolve_names(&dictionary) {
+            Some(resolution) => info!(
+                "Recovered {} names using {:?} of {:?} paths",
+                resolution.names.len(),
+                resolution.hash,
+                resolution.normalization
+            ),
+            None => info!(
+                "The {} names matched too few entries, names stay unresolved",
+                dictionary.len()
+            ),
+        }
+        Ok(reader)
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum OutputFormat {
+    Table,
+    Json,
+    Csv,
 }
 
 /*
@@ -34,17 +393,457 @@ struct MPKInfo {
 } info_header;
 */
 
+fn print_table(entries: &[MPKEntryInfo]) {
+    println!(
+        "{:>7} {:>5} {:>10} {:>10} {:>6} {:>10} {:>10} {:>12} name",
+        "index", "shard", "offset", "size", "folder", "hash", "container", "decompressed"
+    );
+    for entry in entries {
+        let error = match &entry.error {
+            Some(error) => format!(" (error: {})", error),
+            None => String::new(),
+        };
+        println!(
+            "{:>7} {:>5} {:>10} {:>10} {:>6} {:>10} {:>10} {:>12} {}{}",
+            entry.index,
+            entry.shard,
+            entry.offset,
+            entry.size,
+            entry.is_folder,
+            entry.hash.map(|hash| hash.to_string()).unwrap_or_default(),
+            entry.container.as_deref().unwrap_or_default(),
+            entry
+                .decompressed_size
+                .map(|size| size.to_string())
+                .unwrap_or_default(),
+            entry.name,
+            error
+        );
+    }
+}
+
+/// Command line arguments, with `extract` inserted for the `messiah-mpk <mpkinfo> <out_dir>`
+/// invocation from before there were subcommands
+fn legacy_args() -> Vec<std::ffi::OsString> {
+    let mut args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    if let Some(first) = args.get(1).map(|arg| arg.to_string_lossy().into_owned()) {
+        if !first.starts_with('-')
+            && first != "help"
+            && Args::command().find_subcommand(&first).is_none()
+        {
+            args.insert(1, "extract".into());
+        }
+    }
+    args
+}
+
+/// Bytes of a hex string like `deadbeef`, spaces are ignored
+fn parse_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|byte| *byte != b' ').collect();
+    if !digits.len().is_multiple_of(2) {
+        anyhow::bail!("{:?} has an odd number of hex digits", hex);
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| anyhow::anyhow!("{:?} is not a hex string", hex))
+        })
+        .collect()
+}
+
+fn report_summary(summary: &ExtractSummary) -> anyhow::Result<()> {
+    for failure in &summary.failures {
+        error!("{} (#{}): {:#}", failure.name, failure.index, failure.error);
+    }
+    info!(
+        "Extracted: {} | Unchanged: {} | Skipped: {} | Removed: {} | Failed: {}",
+        summary.extracted,
+        summary.unchanged,
+        summary.skipped,
+        summary.removed,
+        summary.failures.len()
+    );
+
+    if !summary.failures.is_empty() {
+        anyhow::bail!("{} entries failed to extract", summary.failures.len());
+    }
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Info)
         .init()
         .unwrap();
 
-    let args = Args::parse();
+    let args = Args::parse_from(legacy_args());
+
+    match args.command {
+        Command::Extract {
+            mpkinfo_file,
+            out_dir,
+            filter,
+            names,
+            threads,
+            keep_going,
+            manifest,
+            incremental,
+            prune,
+            max_entry_size,
+            max_memory,
+        } => {
+            let options = ExtractOptions {
+                filter: filter.to_filter()?,
+                threads,
+                keep_going,
+                manifest: manifest.map(Into::into),
+                format: ExtractFormat::from_path(&out_dir),
+                incremental,
+                prune,
+                max_memory,
+            };
+            let summary = if NPKFileReader::detect(&mpkinfo_file)? {
+                let mut reader = NPKFileReader::new(&mpkinfo_file)?;
+                if let Some(size) = max_entry_size {
+                    reader.codecs_mut().set_max_decoded_size(size);
+                }
+                reader.extract_files_with(out_dir, &options)?
+            } else {
+                let mut reader = names.open_reader(&mpkinfo_file)?;
+                if let Some(size) = max_entry_size {
+                    reader.codecs_mut().set_max_decoded_size(size);
+                }
+                reader.extract_files_with(out_dir, &options)?
+            };
+            report_summary(&summary)?;
+        }
+        Command::List {
+            mpkinfo_file,
+            format,
+            names,
+        } => {
+            let entries = if NPKFileReader::detect(&mpkinfo_file)? {
+                NPKFileReader::new(&mpkinfo_file)?.list_entries()?
+            } else {
+                names.open_reader(&mpkinfo_file)?.list_entries()?
+            };
+            match format {
+                OutputFormat::Table => print_table(&entries),
+                OutputFormat::Json => {
+                    serde_json::to_writer_pretty(std::io::stdout().lock(), &entries)?;
+                    println!();
+                }
+                OutputFormat::Csv => {
+                    let mut writer = csv::Writer::from_writer(std::io::stdout().lock());
+                    for entry in &entries {
+                        writer.serialize(entry)?;
+                    }
+                    writer.flush()?;
+                }
+            }
+            let failed = entries.iter().filter(|entry| entry.error.is_some()).count();
+            if failed > 0 {
+                error!("{} of {} entries failed to decode", failed, entries.len());
+            }
+        }
+        Command::Verify { mpkinfo_file, json } => {
+            let reader = MPKFileReader::new(&mpkinfo_file)?;
+            let results = reader.verify_entries()?;
+            if json {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &results)?;
+                println!();
+            }
+
+            let mut counts = [0; 3];
+            for result in &results {
+                let slot = match &result.status {
+                    MPKVerifyStatus::Verified => 0,
+                    MPKVerifyStatus::Unchecked => 1,
+                    MPKVerifyStatus::Corrupt(_) => 2,
+                };
+                counts[slot] += 1;
+                if let MPKVerifyStatus::Corrupt(reason) = &result.status {
+                    error!("{} ({}): {}", result.name, result.container, reason)
+                }
+            }
+            info!(
+                "Verified: {} | Unchecked: {} | Corrupt: {}",
+                counts[0], counts[1], counts[2]
+            );
+
+            if counts[2] > 0 {
+                anyhow::bail!("{} entries failed verification", counts[2]);
+            }
+        }
+        Command::Diff {
+            old_mpkinfo_file,
+            new_mpkinfo_file,
+            content,
+            json,
+            extract_changed,
+        } => {
+            let old = MPKFileReader::new(&old_mpkinfo_file)?;
+            let new = MPKFileReader::new(&new_mpkinfo_file)?;
+            let diff = MPKDiff::compute(&old, &new, content)?;
+
+            if json {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &diff)?;
+                println!();
+            } else {
+                for name in diff.added.keys() {
+                    println!("+ {}", name);
+                }
+                for name in diff.removed.keys() {
+                    println!("- {}", name);
+                }
+                for change in &diff.changed {
+                    println!(
+                        "~ {} ({} -> {} bytes)",
+                        change.name, change.old.size, change.new.size
+                    );
+                }
+                for change in &diff.failed {
+                    let error = change.old.error.as_ref().or(change.new.error.as_ref());
+                    println!("! {} ({})", change.name, error.cloned().unwrap_or_default());
+                }
+            }
+            info!(
+                "Added: {} | Removed: {} | Changed: {} | Failed: {}",
+                diff.added.len(),
+                diff.removed.len(),
+                diff.changed.len(),
+                diff.failed.len()
+            );
+
+            if let Some(out_dir) = extract_changed {
+                let mut options = ExtractOptions::default();
+                options.filter.add_indices(diff.new_indices());
+                new.extract_files_with(out_dir, &options)?;
+            }
+        }
+        Command::Scan {
+            mpkinfo_file,
+            json,
+            dump_gaps,
+            include_zeroed,
+        } => {
+            let reader = MPKFileReader::new(&mpkinfo_file)?;
+            let report = reader.check_integrity()?;
+
+            if json {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &report)?;
+                println!();
+            } else {
+                for shard in &report.shards {
+                    match shard.size {
+                        Some(size) => println!(
+                            "shard {}: {} entries, {} of {} bytes referenced",
+                            shard.shard, shard.entries, shard.referenced, size
+                        ),
+                        None => println!("shard {}: missing", shard.shard),
+                    }
+                }
+                for gap in &report.gaps {
+                    println!(
+                        "gap in shard {} at {} ({} bytes{})",
+                        gap.shard,
+                        gap.offset,
+                        gap.size,
+                        if gap.zeroed { ", zeroed" } else { "" }
+                    );
+                }
+            }
+            for issue in &report.issues {
+                match issue {
+                    MPKIntegrityIssue::Overlap {
+                        shard,
+                        first,
+                        second,
+                        offset,
+                        size,
+                        identical,
+                    } => error!(
+                        "#{} and #{} share {} bytes at {} in shard {}{}",
+                        first,
+                        second,
+                        size,
+                        offset,
+                        shard,
+                        if *identical { " (identical ranges)" } else { "" }
+                    ),
+                    MPKIntegrityIssue::PastEnd {
+                        index,
+                        shard,
+                        end,
+                        shard_size,
+                    } => error!(
+                        "#{} ends at {} but shard {} only has {} bytes",
+                        index, end, shard, shard_size
+                    ),
+                    MPKIntegrityIssue::MissingShard { shard, entries } => {
+                        error!("shard {} is missing, {} entries refer to it", shard, entries)
+                    }
+                    MPKIntegrityIssue::CompressedShard { shard, entries } => error!(
+                        "shard {} is compressed inside its package, {} entries refer to it",
+                        shard, entries
+                    ),
+                }
+            }
+            info!(
+                "Issues: {} | Gaps: {} ({} bytes)",
+                report.issues.len(),
+                report.gaps.len(),
+                report.gaps.iter().map(|gap| gap.size).sum::<u64>()
+            );
+
+            if let Some(out_dir) = dump_gaps {
+                std::fs::create_dir_all(&out_dir)?;
+                for gap in &report.gaps {
+                    if gap.zeroed && !include_zeroed {
+                        continue;
+                    }
+                    let path = std::path::Path::new(&out_dir)
+                        .join(format!("shard{}_{:08x}.bin", gap.shard, gap.offset));
+                    let mut writer = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                    reader.write_gap(gap, &mut writer)?;
+                    writer.flush()?;
+                }
+            }
+
+            if !report.is_ok() {
+                anyhow::bail!("{} integrity issues found", report.issues.len());
+            }
+        }
+        Command::Carve {
+            mpk_file,
+            out_dir,
+            rebuild_index,
+            json,
+            lookahead,
+        } => {
+            let mut carver = MPKCarver::new();
+            carver.set_lookahead(lookahead);
+            let carve = carver.carve(&mpk_file)?;
+
+            if json {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), carve.entries())?;
+                println!();
+            } else {
+                println!(
+                    "{:>5} {:>10} {:>10} {:>16} {:>5} name",
+                    "shard", "offset", "size", "signature", "exact"
+                );
+                for entry in carve.entries() {
+                    println!(
+                        "{:>5} {:>10} {:>10} {:>16} {:>5} {}",
+                        entry.shard,
+                        entry.offset,
+                        entry.size,
+                        entry
+                            .signature
+                            .map(|signature| format!("{:?}", signature))
+                            .unwrap_or_else(|| "raw".to_string()),
+                        entry.exact,
+                        entry.name
+                    );
+                }
+            }
+            info!(
+                "Recovered {} entries, {} with exact boundaries",
+                carve.entries().len(),
+                carve.entries().iter().filter(|entry| entry.exact).count()
+            );
+
+            if rebuild_index {
+                carve.write_index()?;
+                info!("Wrote {}", carve.info_path().display());
+            }
+            if let Some(out_dir) = out_dir {
+                let options = ExtractOptions {
+                    keep_going: true,
+                    ..Default::default()
+                };
+                let summary = carve.reader().extract_files_with(out_dir, &options)?;
+                report_summary(&summary)?;
+            }
+        }
+        Command::Grep {
+            mpkinfo_file,
+            strings,
+            hex,
+            patterns,
+            filter,
+            names,
+            json,
+        } => {
+            let mut query = GrepQuery::default();
+            for string in &strings {
+                query.add_string(string)?;
+            }
+            for hex in &hex {
+                query.add_bytes(&parse_hex(hex)?)?;
+            }
+            for pattern in &patterns {
+                query.add_regex(pattern)?;
+            }
+            if query.is_empty() {
+                anyhow::bail!("nothing to search for, pass --string, --hex or --pattern");
+            }
+
+            let report = if NPKFileReader::detect(&mpkinfo_file)? {
+                NPKFileReader::new(&mpkinfo_file)?.grep(&query, &filter.to_filter()?)?
+            } else {
+                let reader = names.open_reader(&mpkinfo_file)?;
+                reader.grep(&query, &filter.to_filter()?)?
+            };
+            if json {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &report.hits)?;
+                println!();
+            } else {
+                for hit in &report.hits {
+                    println!("{}:{}: {}", hit.name, hit.offset, hit.pattern);
+                }
+            }
+            for failure in &report.failures {
+                error!("{} (#{}): {:#}", failure.name, failure.index, failure.error);
+            }
+            info!(
+                "Hits: {} | Searched: {} | Failed: {}",
+                report.hits.len(),
+                report.searched,
+                report.failures.len()
+            );
+            if !report.failures.is_empty() {
+                anyhow::bail!("{} entries failed to decode", report.failures.len());
+            }
+        }
+        Command::Patch {
+            mpkinfo_file,
+            dir,
+            compression,
+            undo,
+        } => {
+            if undo {
+                MPKPatcher::undo(&mpkinfo_file)?;
+                info!("Restored {}", mpkinfo_file);
+                return Ok(());
+            }
 
-    let mpkinfo_file = std::path::Path::new(&args.mpkinfo_file);
-    let reader = MPKFileReader::new(mpkinfo_file)?;
-    reader.extract_files(args.out_dir)?;
+            let mut patcher = MPKPatcher::open(&mpkinfo_file)?;
+            patcher.set_compression(compression.into());
+            for (name, action) in patcher.replace_directory(dir.unwrap_or_default())? {
+                info!("{:?} {}", action, name);
+            }
+            let summary = patcher.finish()?;
+            info!(
+                "Replaced: {} | Added: {} | Appended to shards {:?}",
+                summary.replaced, summary.added, summary.shards
+            );
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,146 @@
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use messiah_codec::{Codec, MangledZlib};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// Where an extracted file came from, written by extraction with
+/// [`crate::ExtractOptions::manifest`] set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub index: usize,
+    /// Name of the entry in the index
+    pub entry_name: String,
+    /// Path the entry was written to, relative to the output directory. Differs from
+    /// `entry_name` when the name was detected from the content
+    pub path: String,
+    pub is_folder: bool,
+    pub shard: u32,
+    pub offset: u64,
+    /// Size of the payload inside the shard
+    pub size: u64,
+    /// Name of the container the payload is stored in, `None` for folders
+    pub container: Option<String>,
+    /// Hex encoded tail of `\xE2\x06` mangled zlib payloads, needed to store them again
+    /// byte for byte
+    pub tail: Option<String>,
+    /// Flags of version 2 index entries
+    pub flags: Option<u32>,
+    /// Name hash, stored by version 2 indices and NPK archives
+    pub hash: Option<u32>,
+    /// SHA-1 of the stored payload
+    pub stored_digest: Option<String>,
+    /// Size of the written file
+    pub decoded_size: Option<usize>,
+    /// SHA-1 of the written file
+    pub digest: Option<String>,
+    /// A later entry was written to the same path, the file holds that one instead
+    pub overwritten: bool,
+}
+
+impl ManifestEntry {
+    /// Record for a folder, only version 1 indices have them
+    pub(crate) fn folder(index: usize, name: String) -> Self {
+        Self {
+            index,
+            path: name.clone(),
+            entry_name: name,
+            is_folder: true,
+            shard: 0,
+            offset: 0,
+            size: 0,
+            container: None,
+            tail: None,
+            flags: None,
+            hash: None,
+            stored_digest: None,
+            decoded_size: None,
+            digest: None,
+            overwritten: false,
+        }
+    }
+
+    /// Record for a file, `stored` is the payload as found in the shard and
+    /// `decoded` what gets written to `path`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn file(
+        index: usize,
+        entry_name: String,
+        path: String,
+        shard: u32,
+        offset: u64,
+        container: &str,
+        stored: &[u8],
+        decoded: &[u8],
+    ) -> Self {
+        let tail = if container == MangledZlib.name() {
+            MangledZlib.tail(stored).ok().map(|tail| hex(&tail))
+        } else {
+            None
+        };
+        Self {
+            index,
+            entry_name,
+            path,
+            is_folder: false,
+            shard,
+            offset,
+            size: stored.len() as u64,
+            container: Some(container.to_string()),
+            tail,
+            flags: None,
+            hash: None,
+            stored_digest: Some(hex(&Sha1::digest(stored))),
+            decoded_size: Some(decoded.len()),
+            digest: Some(hex(&Sha1::digest(decoded))),
+            overwritten: false,
+        }
+    }
+}
+
+/// Every entry an extraction wrote, in index order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractManifest {
+    /// The `.mpkinfo` or `.npk` file the entries were extracted from
+    pub archive: PathBuf,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl ExtractManifest {
+    /// Where incremental extraction keeps the manifest unless told otherwise,
+    /// `out.manifest.json` next to the output directory `out`
+    pub fn default_path<P: AsRef<Path>>(out_dir: P) -> PathBuf {
+        let out_dir = out_dir.as_ref();
+        match out_dir.file_name() {
+            Some(name) => {
+                out_dir.with_file_name(format!("{}.manifest.json", name.to_string_lossy()))
+            }
+            None => out_dir.join(".manifest.json"),
+        }
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Failed to parse manifest {}", path.display()))
+    }
+
+    /// Writes the manifest as JSON to `path`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create manifest {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
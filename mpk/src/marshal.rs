@@ -0,0 +1,501 @@
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::MPKError;
+
+/// Deepest nesting we unmarshal. CPython allows 2000, but every level takes several
+/// frames of our own stack and compiled code never nests more than a few levels
+const MAX_DEPTH: usize = 200;
+
+/// Set on the type byte of objects later referenced by `TYPE_REF`, since 3.4
+const FLAG_REF: u8 = 0x80;
+
+const TYPE_NULL: u8 = b'0';
+const TYPE_NONE: u8 = b'N';
+const TYPE_FALSE: u8 = b'F';
+const TYPE_TRUE: u8 = b'T';
+const TYPE_STOPITER: u8 = b'S';
+const TYPE_ELLIPSIS: u8 = b'.';
+const TYPE_INT: u8 = b'i';
+const TYPE_INT64: u8 = b'I';
+const TYPE_FLOAT: u8 = b'f';
+const TYPE_BINARY_FLOAT: u8 = b'g';
+const TYPE_COMPLEX: u8 = b'x';
+const TYPE_BINARY_COMPLEX: u8 = b'y';
+const TYPE_LONG: u8 = b'l';
+const TYPE_STRING: u8 = b's';
+const TYPE_INTERNED: u8 = b't';
+const TYPE_STRINGREF: u8 = b'R';
+const TYPE_REF: u8 = b'r';
+const TYPE_TUPLE: u8 = b'(';
+const TYPE_LIST: u8 = b'[';
+const TYPE_DICT: u8 = b'{';
+const TYPE_CODE: u8 = b'c';
+const TYPE_UNICODE: u8 = b'u';
+const TYPE_SET: u8 = b'<';
+const TYPE_FROZENSET: u8 = b'>';
+const TYPE_ASCII: u8 = b'a';
+const TYPE_ASCII_INTERNED: u8 = b'A';
+const TYPE_SMALL_TUPLE: u8 = b')';
+const TYPE_SHORT_ASCII: u8 = b'z';
+const TYPE_SHORT_ASCII_INTERNED: u8 = b'Z';
+
+/// Python release a pyc was compiled by, detected from its magic number
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PythonVersion {
+    Version2_0,
+    Version2_1,
+    Version2_2,
+    Version2_3,
+    Version2_4,
+    Version2_5,
+    Version2_6,
+    Version2_7,
+    Version3_0,
+    Version3_1,
+    Version3_2,
+    Version3_3,
+    Version3_4,
+    Version3_5,
+    Version3_6,
+    Version3_7,
+    Version3_8,
+    Version3_9,
+    Version3_10,
+    Version3_11,
+    Version3_12,
+    Version3_13,
+}
+
+impl PythonVersion {
+    /// Size of the header in front of the marshalled code object of a pyc, the magic
+    /// and timestamp, plus the source size since 3.3 and flags since 3.7
+    pub fn pyc_header_size(self) -> usize {
+        if self >= PythonVersion::Version3_7 {
+            16
+        } else if self >= PythonVersion::Version3_3 {
+            12
+        } else {
+            8
+        }
+    }
+}
+
+/// Arbitrary precision integer, stored as 15 bit digits with the least significant first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PyLong {
+    pub negative: bool,
+    pub digits: Vec<u16>,
+}
+
+impl PyLong {
+    /// The value, unless it doesn't fit
+    pub fn to_i128(&self) -> Option<i128> {
+        let mut value: i128 = 0;
+        for digit in self.digits.iter().rev() {
+            value = value.checked_mul(1 << 15)?.checked_add(*digit as i128)?;
+        }
+        Some(if self.negative { -value } else { value })
+    }
+}
+
+/// A code object, fields a Python version doesn't marshal are `None`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PyCode {
+    pub argcount: u32,
+    /// Since 3.8
+    pub posonlyargcount: Option<u32>,
+    /// Since 3.0
+    pub kwonlyargcount: Option<u32>,
+    /// Up to 3.10
+    pub nlocals: Option<u32>,
+    pub stacksize: u32,
+    pub flags: u32,
+    /// The bytecode
+    pub code: PyObject,
+    pub consts: PyObject,
+    pub names: PyObject,
+    /// Up to 3.10, replaced by `localsplusnames`
+    pub varnames: Option<PyObject>,
+    /// From 2.1 up to 3.10
+    pub freevars: Option<PyObject>,
+    /// From 2.1 up to 3.10
+    pub cellvars: Option<PyObject>,
+    /// Since 3.11
+    pub localsplusnames: Option<PyObject>,
+    /// Since 3.11
+    pub localspluskinds: Option<PyObject>,
+    pub filename: PyObject,
+    pub name: PyObject,
+    /// Since 3.11
+    pub qualname: Option<PyObject>,
+    pub firstlineno: u32,
+    /// `co_lnotab`, called `co_linetable` since 3.10
+    pub linetable: PyObject,
+    /// Since 3.11
+    pub exceptiontable: Option<PyObject>,
+}
+
+/// An object read from `marshal` data, e.g. the module code object of a pyc.
+///
+/// Objects the data marks for reuse show up as [`PyObject::Ref`] wherever they are
+/// used, all of them sharing the same object. See [`PyObject::resolve`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PyObject {
+    /// End marker of dicts, also used for missing values
+    Null,
+    None,
+    False,
+    True,
+    StopIteration,
+    Ellipsis,
+    Int(i64),
+    Long(PyLong),
+    Float(f64),
+    Complex(f64, f64),
+    /// `str` in Python 2, `bytes` in Python 3
+    Bytes(Vec<u8>),
+    /// `unicode` in Python 2, `str` in Python 3
+    Str(String),
+    Tuple(Vec<PyObject>),
+    List(Vec<PyObject>),
+    Dict(Vec<(PyObject, PyObject)>),
+    Set(Vec<PyObject>),
+    FrozenSet(Vec<PyObject>),
+    Code(Box<PyCode>),
+    /// An object flagged for reuse, or a Python 2 interned string
+    Ref(Arc<PyObject>),
+}
+
+impl PyObject {
+    /// Reads the object at the start of `data`, written by `version`
+    pub fn from_marshal(data: &[u8], version: PythonVersion) -> Result<Self, MPKError> {
+        MarshalReader {
+            data,
+            position: 0,
+            version,
+            refs: Vec::new(),
+            interned: Vec::new(),
+            depth: 0,
+        }
+        .read_object()
+    }
+
+    /// The object behind any [`PyObject::Ref`]
+    pub fn resolve(&self) -> &PyObject {
+        match self {
+            PyObject::Ref(object) => object.resolve(),
+            object => object,
+        }
+    }
+
+    /// Text of `Str` objects and of `Bytes` holding UTF-8, like Python 2 names
+    pub fn as_str(&self) -> Option<&str> {
+        match self.resolve() {
+            PyObject::Str(text) => Some(text),
+            PyObject::Bytes(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_code(&self) -> Option<&PyCode> {
+        match self.resolve() {
+            PyObject::Code(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+struct MarshalReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    version: PythonVersion,
+    /// Objects flagged with `FLAG_REF`, `None` while they are still being read
+    refs: Vec<Option<Arc<PyObject>>>,
+    /// Python 2 interned strings, referenced by `TYPE_STRINGREF`
+    interned: Vec<Arc<PyObject>>,
+    depth: usize,
+}
+
+impl<'a> MarshalReader<'a> {
+    fn error(&self, message: &str) -> MPKError {
+        MPKError::InvalidMarshal(format!("{} at offset {}", message, self.position))
+    }
+
+    fn bytes(&mut self, size: usize) -> Result<&'a [u8], MPKError> {
+        let bytes = self
+            .position
+            .checked_add(size)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(|| self.error("data ends early"))?;
+        self.position += size;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, MPKError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MPKError> {
+        Ok(LittleEndian::read_u16(self.bytes(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32, MPKError> {
+        Ok(LittleEndian::read_u32(self.bytes(4)?))
+    }
+
+    fn i32(&mut self) -> Result<i32, MPKError> {
+        Ok(LittleEndian::read_i32(self.bytes(4)?))
+    }
+
+    /// Counts and sizes, only ever as large as the data left
+    fn size(&mut self) -> Result<usize, MPKError> {
+        let size = self.u32()? as usize;
+        if size > self.data.len() - self.position {
+            return Err(self.error("size runs past the end of the data"));
+        }
+        Ok(size)
+    }
+
+    /// Counts of code objects, shorts before 2.3
+    fn count(&mut self) -> Result<u32, MPKError> {
+        match self.version < PythonVersion::Version2_3 {
+            true => Ok(self.u16()? as u32),
+            false => self.u32(),
+        }
+    }
+
+    fn f64(&mut self) -> Result<f64, MPKError> {
+        Ok(LittleEndian::read_f64(self.bytes(8)?))
+    }
+
+    /// A float written as text with a one byte length
+    fn text_float(&mut self) -> Result<f64, MPKError> {
+        let size = self.u8()? as usize;
+        let text = self.bytes(size)?;
+        std::str::from_utf8(text)
+            .ok()
+            .and_then(|text| text.trim().parse().ok())
+            .ok_or_else(|| self.error("invalid float"))
+    }
+
+    fn text(&mut self, size: usize) -> Result<String, MPKError> {
+        Ok(String::from_utf8_lossy(self.bytes(size)?).into_owned())
+    }
+
+    fn objects(&mut self, count: usize) -> Result<Vec<PyObject>, MPKError> {
+        (0..count).map(|_| self.read_object()).collect()
+    }
+
+    fn read_object(&mut self) -> Result<PyObject, MPKError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("objects nested too deep"));
+        }
+        self.depth += 1;
+        let object = self.read_flagged();
+        self.depth -= 1;
+        object
+    }
+
+    fn read_flagged(&mut self) -> Result<PyObject, MPKError> {
+        let flagged = self.u8()?;
+        let (t, is_ref) = (flagged & !FLAG_REF, flagged & FLAG_REF != 0);
+        // Containers reserve their slot before their items are read
+        let slot = is_ref.then(|| {
+            self.refs.push(None);
+            self.refs.len() - 1
+        });
+        let object = self.read_typed(t)?;
+        let Some(slot) = slot else {
+            return Ok(object);
+        };
+        // Shared instead of copied, every reference would copy the whole object again
+        let object = Arc::new(object);
+        self.refs[slot] = Some(object.clone());
+        Ok(PyObject::Ref(object))
+    }
+
+    /// Containers, everything else is read by [`MarshalReader::read_leaf`] to keep this
+    /// frame small, it is on the stack once per nesting level
+    fn read_typed(&mut self, t: u8) -> Result<PyObject, MPKError> {
+        let object = match t {
+            TYPE_TUPLE => {
+                let count = self.size()?;
+                PyObject::Tuple(self.objects(count)?)
+            }
+            TYPE_SMALL_TUPLE => {
+                let count = self.u8()? as usize;
+                PyObject::Tuple(self.objects(count)?)
+            }
+            TYPE_LIST => {
+                let count = self.size()?;
+                PyObject::List(self.objects(count)?)
+            }
+            TYPE_SET => {
+                let count = self.size()?;
+                PyObject::Set(self.objects(count)?)
+            }
+            TYPE_FROZENSET => {
+                let count = self.size()?;
+                PyObject::FrozenSet(self.objects(count)?)
+            }
+            TYPE_DICT => {
+                let mut items = Vec::new();
+                loop {
+                    let key = self.read_object()?;
+                    if matches!(key.resolve(), PyObject::Null) {
+                        break;
+                    }
+                    items.push((key, self.read_object()?));
+                }
+                PyObject::Dict(items)
+            }
+            TYPE_CODE => PyObject::Code(self.read_code()?),
+            _ => return self.read_leaf(t),
+        };
+        Ok(object)
+    }
+
+    fn read_leaf(&mut self, t: u8) -> Result<PyObject, MPKError> {
+        let object = match t {
+            TYPE_NULL => PyObject::Null,
+            TYPE_NONE => PyObject::None,
+            TYPE_FALSE => PyObject::False,
+            TYPE_TRUE => PyObject::True,
+            TYPE_STOPITER => PyObject::StopIteration,
+            TYPE_ELLIPSIS => PyObject::Ellipsis,
+            TYPE_INT => PyObject::Int(self.i32()? as i64),
+            TYPE_INT64 => PyObject::Int(LittleEndian::read_i64(self.bytes(8)?)),
+            TYPE_FLOAT => PyObject::Float(self.text_float()?),
+            TYPE_BINARY_FLOAT => PyObject::Float(self.f64()?),
+            TYPE_COMPLEX => PyObject::Complex(self.text_float()?, self.text_float()?),
+            TYPE_BINARY_COMPLEX => PyObject::Complex(self.f64()?, self.f64()?),
+            TYPE_LONG => {
+                let size = self.i32()?;
+                let count = size.unsigned_abs() as usize;
+                if count > (self.data.len() - self.position) / 2 {
+                    return Err(self.error("long runs past the end of the data"));
+                }
+                let digits = (0..count).map(|_| self.u16()).collect::<Result<_, _>>()?;
+                PyObject::Long(PyLong {
+                    negative: size < 0,
+                    digits,
+                })
+            }
+            TYPE_STRING => {
+                let size = self.size()?;
+                PyObject::Bytes(self.bytes(size)?.to_vec())
+            }
+            TYPE_INTERNED if self.version < PythonVersion::Version3_0 => {
+                let size = self.size()?;
+                let object = Arc::new(PyObject::Bytes(self.bytes(size)?.to_vec()));
+                self.interned.push(object.clone());
+                PyObject::Ref(object)
+            }
+            TYPE_STRINGREF if self.version < PythonVersion::Version3_0 => {
+                let index = self.u32()? as usize;
+                let object = self
+                    .interned
+                    .get(index)
+                    .cloned()
+                    .ok_or_else(|| self.error("unknown interned string"))?;
+                PyObject::Ref(object)
+            }
+            TYPE_UNICODE | TYPE_INTERNED | TYPE_ASCII | TYPE_ASCII_INTERNED => {
+                let size = self.size()?;
+                PyObject::Str(self.text(size)?)
+            }
+            TYPE_SHORT_ASCII | TYPE_SHORT_ASCII_INTERNED => {
+                let size = self.u8()? as usize;
+                PyObject::Str(self.text(size)?)
+            }
+            TYPE_REF => {
+                let index = self.u32()? as usize;
+                match self.refs.get(index) {
+                    Some(Some(object)) => PyObject::Ref(object.clone()),
+                    Some(None) => return Err(self.error("reference to an unfinished object")),
+                    None => return Err(self.error("unknown reference")),
+                }
+            }
+            _ => {
+                self.position -= 1;
+                return Err(self.error(&format!("unknown object type {:#04x}", t)));
+            }
+        };
+        Ok(object)
+    }
+
+    fn read_code(&mut self) -> Result<Box<PyCode>, MPKError> {
+        use PythonVersion::*;
+
+        let version = self.version;
+        let argcount = self.count()?;
+        let posonlyargcount = match version >= Version3_8 {
+            true => Some(self.u32()?),
+            false => None,
+        };
+        let kwonlyargcount = match version >= Version3_0 {
+            true => Some(self.u32()?),
+            false => None,
+        };
+        let nlocals = match version < Version3_11 {
+            true => Some(self.count()?),
+            false => None,
+        };
+        let stacksize = self.count()?;
+        let flags = self.count()?;
+        let code = self.read_object()?;
+        let consts = self.read_object()?;
+        let names = self.read_object()?;
+
+        let (mut varnames, mut freevars, mut cellvars) = (None, None, None);
+        let (mut localsplusnames, mut localspluskinds) = (None, None);
+        if version < Version3_11 {
+            varnames = Some(self.read_object()?);
+            if version >= Version2_1 {
+                freevars = Some(self.read_object()?);
+                cellvars = Some(self.read_object()?);
+            }
+        } else {
+            localsplusnames = Some(self.read_object()?);
+            localspluskinds = Some(self.read_object()?);
+        }
+
+        let filename = self.read_object()?;
+        let name = self.read_object()?;
+        let qualname = match version >= Version3_11 {
+            true => Some(self.read_object()?),
+            false => None,
+        };
+        let firstlineno = self.count()?;
+        let linetable = self.read_object()?;
+        let exceptiontable = match version >= Version3_11 {
+            true => Some(self.read_object()?),
+            false => None,
+        };
+
+        Ok(Box::new(PyCode {
+            argcount,
+            posonlyargcount,
+            kwonlyargcount,
+            nlocals,
+            stacksize,
+            flags,
+            code,
+            consts,
+            names,
+            varnames,
+            freevars,
+            cellvars,
+            localsplusnames,
+            localspluskinds,
+            filename,
+            name,
+            qualname,
+            firstlineno,
+            linetable,
+            exceptiontable,
+        }))
+    }
+}
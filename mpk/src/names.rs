@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+use anyhow::Context;
+use serde::Serialize;
+use try_insert_ext::EntryInsertExt;
+
+use crate::{helpers, ExtractFailure, MPKFileEntry, MPKFileReader};
+
+/// Hash functions version 2 indices may use for the name hash.
+///
+/// The function the engine uses couldn't be identified, no archive with known paths was
+/// available to check the candidates against. Instead of a fixed function
+/// [`NameDictionary::resolve`] tries all of these and picks the one matching the most
+/// entries, which only works with a dictionary that holds enough of the real paths.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameHash {
+    Crc32,
+    Fnv1a32,
+    Fnv1_32,
+    Djb2,
+    Sdbm,
+    Bkdr,
+    Java,
+    Murmur3,
+}
+
+impl NameHash {
+    pub const ALL: [NameHash; 8] = [
+        NameHash::Crc32,
+        NameHash::Fnv1a32,
+        NameHash::Fnv1_32,
+        NameHash::Djb2,
+        NameHash::Sdbm,
+        NameHash::Bkdr,
+        NameHash::Java,
+        NameHash::Murmur3,
+    ];
+
+    pub fn hash(&self, name: &str) -> u32 {
+        let bytes = name.as_bytes();
+        match self {
+            NameHash::Crc32 => crc32fast::hash(bytes),
+            NameHash::Fnv1a32 => bytes.iter().fold(0x811c9dc5, |hash: u32, b| {
+                (hash ^ *b as u32).wrapping_mul(0x01000193)
+            }),
+            NameHash::Fnv1_32 => bytes.iter().fold(0x811c9dc5, |hash: u32, b| {
+                hash.wrapping_mul(0x01000193) ^ *b as u32
+            }),
+            NameHash::Djb2 => bytes.iter().fold(5381, |hash: u32, b| {
+                hash.wrapping_mul(33).wrapping_add(*b as u32)
+            }),
+            NameHash::Sdbm => bytes.iter().fold(0, |hash: u32, b| {
+                (*b as u32)
+                    .wrapping_add(hash << 6)
+                    .wrapping_add(hash << 16)
+                    .wrapping_sub(hash)
+            }),
+            NameHash::Bkdr => bytes.iter().fold(0, |hash: u32, b| {
+                hash.wrapping_mul(131).wrapping_add(*b as u32)
+            }),
+            NameHash::Java => bytes.iter().fold(0, |hash: u32, b| {
+                hash.wrapping_mul(31).wrapping_add(*b as u32)
+            }),
+            NameHash::Murmur3 => murmur3_32(bytes, 0),
+        }
+    }
+}
+
+fn murmur3_32(bytes: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut hash = seed;
+    let mut chunks = bytes.chunks_exact(4);
+    for chunk in &mut chunks {
+        let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe6546b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0, |k: u32, b| (k << 8) | *b as u32);
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+    hash ^= bytes.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85ebca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2ae35);
+    hash ^ (hash >> 16)
+}
+
+/// How a path is spelled before hashing it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameNormalization {
+    AsIs,
+    Lowercase,
+    Backslashes,
+    LowercaseBackslashes,
+}
+
+impl NameNormalization {
+    pub const ALL: [NameNormalization; 4] = [
+        NameNormalization::AsIs,
+        NameNormalization::Lowercase,
+        NameNormalization::Backslashes,
+        NameNormalization::LowercaseBackslashes,
+    ];
+
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            NameNormalization::AsIs => name.to_string(),
+            NameNormalization::Lowercase => name.to_lowercase(),
+            NameNormalization::Backslashes => name.replace('/', "\\"),
+            NameNormalization::LowercaseBackslashes => name.to_lowercase().replace('/', "\\"),
+        }
+    }
+}
+
+/// Names recovered for a version 2 index
+#[derive(Debug, Clone, Serialize)]
+pub struct NameResolution {
+    pub hash: NameHash,
+    pub normalization: NameNormalization,
+    /// Original path by name hash
+    pub names: HashMap<u32, String>,
+}
+
+/// Candidate paths for the entries of version 2 indices, which only store a name hash
+#[derive(Debug, Default, Clone)]
+pub struct NameDictionary {
+    names: HashSet<String>,
+}
+
+impl NameDictionary {
+    /// Entries a hash function and spelling have to match before their names are used,
+    /// unless they match most entries of a smaller archive. A single match may just be
+    /// a collision with a large dictionary.
+    pub const MIN_MATCHES: usize = 3;
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn add_name(&mut self, name: &str) {
+        let name = name.trim().replace('\\', "/");
+        if !name.is_empty() {
+            self.names.insert(name);
+        }
+    }
+
+    /// Adds a word list with one path per line
+    pub fn add_word_list<P: AsRef<std::path::Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to read word list {}", path.as_ref().display()))?;
+        for line in std::io::BufReader::new(file).lines() {
+            self.add_name(&line?);
+        }
+        Ok(())
+    }
+
+    /// Adds the path of every file known to a resource repository
+    pub fn add_repository(&mut self, repository: &messiah_resources::Repository) {
+        for file in repository.files() {
+            self.add_name(&file.file_path());
+        }
+    }
+
+    /// Adds the `co_filename` of every script in the archive, with the script folder
+    /// prefix extraction uses and without it.
+    ///
+    /// Entries whose payload can't be read, e.g. because their shard is missing, are
+    /// skipped and returned.
+    pub fn add_python_names(&mut self, reader: &MPKFileReader) -> Vec<ExtractFailure> {
+        let mut mpk_map = HashMap::new();
+        let mut failures = Vec::new();
+        for (index, file) in reader.files.iter().enumerate() {
+            if file.is_folder() {
+                continue;
+            }
+            let file_number = file.file_number();
+            let file_buffer = mpk_map
+                .entry(file_number)
+                .or_try_insert_with(|| reader.open_shard(file_number))
+                .and_then(|mpk_file| reader.read_entry(mpk_file, file));
+            let file_buffer = match file_buffer {
+                Ok(file_buffer) => file_buffer,
+                Err(err) => {
+                    failures.push(ExtractFailure {
+                        index,
+                        name: reader.entry_name(file),
+                        error: err.into(),
+                    });
+                    continue;
+                }
+            };
+            let Some(codec) = reader.codecs.detect(&file_buffer) else {
+                continue;
+            };
+            if !codec.sniff_name() {
+                continue;
+            }
+            let limit = reader.codecs.max_decoded_size();
+            let Ok(file_buffer) = codec.decode_limited(&file_buffer, limit) else {
+                continue;
+            };
+            if let Ok(file_name) = helpers::file_name_from_py_buffer(&file_buffer) {
+                if !file_name.is_empty() {
+                    self.add_name(&file_name);
+                    self.add_name(&format!("{}c", file_name));
+                    self.add_name(&format!("Script/Python/{}", file_name));
+                    self.add_name(&format!("Script/Python/{}c", file_name));
+                }
+            }
+        }
+        failures
+    }
+
+    /// Finds the hash function and spelling matching most entries of `reader` and
+    /// returns the names it resolves, `None` if no candidate matches at least
+    /// [`NameDictionary::MIN_MATCHES`] entries or more than half of them
+    pub fn resolve(&self, reader: &MPKFileReader) -> Option<NameResolution> {
+        let entries: HashMap<u32, [u8; 3]> = reader
+            .files
+            .iter()
+            .filter_map(|file| match file {
+                MPKFileEntry::V2(entry) if !file.is_folder() => Some((entry.hash, entry.name)),
+                _ => None,
+            })
+            .collect();
+
+        let mut best: Option<NameResolution> = None;
+        for hash in NameHash::ALL {
+            for normalization in NameNormalization::ALL {
+                let mut names = HashMap::new();
+                for name in &self.names {
+                    let value = hash.hash(&normalization.apply(name));
+                    if let Some(extension) = entries.get(&value) {
+                        if Self::extension_matches(extension, name) {
+                            names.insert(value, name.clone());
+                        }
+                    }
+                }
+                let trusted = names.len() >= Self::MIN_MATCHES || names.len() * 2 > entries.len();
+                if trusted
+                    && best
+                        .as_ref()
+                        .is_none_or(|best| names.len() > best.names.len())
+                {
+                    best = Some(NameResolution {
+                        hash,
+                        normalization,
+                        names,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    fn extension_matches(extension: &[u8; 3], name: &str) -> bool {
+        let extension = String::from_utf8_lossy(extension);
+        let extension = extension.trim_end_matches('\0');
+        let name_extension = std::path::Path::new(name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        extension.is_empty() || name_extension.starts_with(&extension.to_lowercase())
+    }
+}
@@ -0,0 +1,439 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use byteorder::{ByteOrder, LittleEndian};
+use memmap2::Mmap;
+use messiah_codec::{decompress_block, Codec, CodecRegistry, Zlib};
+use serde::Serialize;
+
+use crate::extract::{extract_parallel, Extracted};
+use crate::grep::grep_parallel;
+use crate::incremental::IncrementalState;
+use crate::{
+    decode_payload, sniff_file_name, ExtractFilter, ExtractOptions, ExtractSummary, GrepQuery,
+    MPKEntryInfo, MPKEntryReader, MPKError, MPKGrepReport, ManifestEntry, SignatureRegistry,
+};
+
+const NPK_MAGIC: &[u8; 4] = b"NXPK";
+const HEADER_SIZE: usize = 24;
+/// Hash, offset, size, original size, compressed CRC, CRC and flags, all `u32`
+const ENTRY_SIZE: usize = 28;
+/// Entries of archives with both `var1` and `var3` set carry 12 more bytes we don't know,
+/// the first 28 bytes are laid out like [`ENTRY_SIZE`] entries
+const EXTENDED_ENTRY_SIZE: usize = 40;
+/// Unknown bytes between the index and the name table
+const NAMES_PADDING: usize = 16;
+
+/// How an NPK entry is compressed, taken from the low half of its flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NPKCompression {
+    None,
+    Zlib,
+    /// Raw lz4 block, the entry stores the decompressed size
+    Lz4,
+    Unknown(u16),
+}
+
+impl From<u16> for NPKCompression {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => NPKCompression::None,
+            1 => NPKCompression::Zlib,
+            2 => NPKCompression::Lz4,
+            value => NPKCompression::Unknown(value),
+        }
+    }
+}
+
+/// A single entry of an `.npk` index
+#[derive(Debug, Clone, Serialize)]
+pub struct NPKEntry {
+    /// Name hash
+    pub hash: u32,
+    pub offset: u32,
+    /// Size of the payload inside the archive
+    pub size: u32,
+    /// Size after undoing [`NPKEntry::compression`]
+    pub original_size: u32,
+    pub compressed_crc: u32,
+    pub crc: u32,
+    pub compression: NPKCompression,
+    /// Encryption mode from the high half of the flags, `0` for plain entries
+    pub encryption: u16,
+    /// Path from the name table, if the archive has one
+    pub name: Option<String>,
+}
+
+/// Reader for the `.npk` (`NXPK`) archives of the older NeoX engine.
+///
+/// Unlike MPK archives index and payloads live in the same file. The header is
+/// `NXPK`, the entry count, three unknown words and the offset of the index.
+/// Payloads are decoded with the same codecs and named the same way as MPK entries.
+#[derive(Debug)]
+pub struct NPKFileReader {
+    path: PathBuf,
+    codecs: CodecRegistry,
+    signatures: SignatureRegistry,
+    data: Mmap,
+    entries: Vec<NPKEntry>,
+}
+
+impl NPKFileReader {
+    /// Whether the file at `path` starts with the `NXPK` magic, `false` if there is
+    /// no such file, like for paths into a package
+    pub fn detect<P: AsRef<Path>>(path: P) -> anyhow::Result<bool> {
+        use std::io::Read;
+
+        if !path.as_ref().exists() {
+            return Ok(false);
+        }
+        let mut magic = [0; 4];
+        let mut file = std::fs::File::open(&path)?;
+        Ok(file.read_exact(&mut magic).is_ok() && &magic == NPK_MAGIC)
+    }
+
+    pub fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(&path).with_context(|| {
+            format!(
+                "Failed to read .npk file from {}",
+                path.as_ref().to_string_lossy()
+            )
+        })?;
+        // SAFETY: see `Shard::map`
+        let data = unsafe { Mmap::map(&file) }?;
+
+        let header = data
+            .get(..HEADER_SIZE)
+            .filter(|header| header.starts_with(NPK_MAGIC))
+            .ok_or(MPKError::InvalidNPKHeader())?;
+        let file_count = LittleEndian::read_u32(&header[4..8]);
+        let var1 = LittleEndian::read_u32(&header[8..12]);
+        let var3 = LittleEndian::read_u32(&header[16..20]);
+        let index_offset = LittleEndian::read_u32(&header[20..24]) as usize;
+        let entry_size = if var1 != 0 && var3 != 0 {
+            EXTENDED_ENTRY_SIZE
+        } else {
+            ENTRY_SIZE
+        };
+
+        let mut entries = Vec::new();
+        for read in 0..file_count {
+            let entry = (read as usize)
+                .checked_mul(entry_size)
+                .and_then(|start| start.checked_add(index_offset))
+                .and_then(|start| data.get(start..start.checked_add(ENTRY_SIZE)?))
+                .ok_or(MPKError::TruncatedIndex {
+                    read,
+                    count: file_count,
+                })?;
+            let flags = LittleEndian::read_u32(&entry[24..28]);
+            entries.push(NPKEntry {
+                hash: LittleEndian::read_u32(&entry[0..4]),
+                offset: LittleEndian::read_u32(&entry[4..8]),
+                size: LittleEndian::read_u32(&entry[8..12]),
+                original_size: LittleEndian::read_u32(&entry[12..16]),
+                compressed_crc: LittleEndian::read_u32(&entry[16..20]),
+                crc: LittleEndian::read_u32(&entry[20..24]),
+                compression: NPKCompression::from(flags as u16),
+                encryption: (flags >> 16) as u16,
+                name: None,
+            });
+        }
+
+        // The index fits into the mapped file, so this can't overflow
+        let names_offset = index_offset + entries.len() * entry_size + NAMES_PADDING;
+        if let Some(names) = Self::read_names(&data, names_offset, entries.len()) {
+            for (entry, name) in entries.iter_mut().zip(names) {
+                entry.name = Some(name);
+            }
+        }
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            codecs: CodecRegistry::default(),
+            signatures: SignatureRegistry::default(),
+            data,
+            entries,
+        })
+    }
+
+    /// Some archives store a NUL terminated path per entry [`NAMES_PADDING`] bytes behind
+    /// the index, only used if there is a valid one for every entry
+    fn read_names(data: &[u8], offset: usize, count: usize) -> Option<Vec<String>> {
+        if count == 0 {
+            return None;
+        }
+        let names: Vec<String> = data
+            .get(offset..)?
+            .split(|byte| *byte == 0)
+            .take(count)
+            .map(|name| {
+                std::str::from_utf8(name)
+                    .ok()
+                    .map(|name| name.replace('\\', "/"))
+            })
+            .collect::<Option<_>>()?;
+        if names.len() != count || names.iter().any(|name| name.is_empty()) {
+            return None;
+        }
+        Some(names)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Codecs used to decode entry payloads, register new ones here before extracting
+    pub fn codecs_mut(&mut self) -> &mut CodecRegistry {
+        &mut self.codecs
+    }
+
+    /// File types recognised when naming entries without a name
+    pub fn signatures_mut(&mut self) -> &mut SignatureRegistry {
+        &mut self.signatures
+    }
+
+    pub fn entries(&self) -> &[NPKEntry] {
+        &self.entries
+    }
+
+    /// Path from the name table, `file_{index}_{hash}` without one
+    pub fn entry_name(&self, index: usize) -> String {
+        let entry = &self.entries[index];
+        match &entry.name {
+            Some(name) => name.clone(),
+            None => format!("file_{}_{}", index, entry.hash),
+        }
+    }
+
+    /// Reads and decodes a single entry
+    pub fn open_entry(&self, index: usize) -> anyhow::Result<MPKEntryReader> {
+        if index >= self.entries.len() {
+            return Err(MPKError::EntryNotFound(index.to_string()).into());
+        }
+        let (file_buffer, file_name) = self.decode_entry(index)?;
+        Ok(MPKEntryReader::new(file_name, file_buffer.into_owned()))
+    }
+
+    /// Summary of every entry in the same shape `messiah-mpk list` uses for MPK archives,
+    /// entries that fail to decode are listed with their error
+    pub fn list_entries(&self) -> anyhow::Result<Vec<MPKEntryInfo>> {
+        let mut infos = Vec::with_capacity(self.entries.len());
+        for (index, entry) in self.entries.iter().enumerate() {
+            // Encrypted entries are listed, just without anything we'd need to decode
+            let (decompressed_size, name, error) = if entry.encryption != 0 {
+                (None, self.entry_name(index), None)
+            } else {
+                match self.decode_entry(index) {
+                    Ok((file_buffer, name)) => (Some(file_buffer.len()), name, None),
+                    Err(err) => (None, self.entry_name(index), Some(format!("{:#}", err))),
+                }
+            };
+            infos.push(MPKEntryInfo {
+                index,
+                shard: 0,
+                offset: entry.offset,
+                size: entry.size,
+                is_folder: false,
+                hash: Some(entry.hash),
+                container: Some(self.container_name(index)),
+                decompressed_size,
+                name,
+                error,
+            });
+        }
+        Ok(infos)
+    }
+
+    pub fn extract_files<P: AsRef<Path>>(&self, out_dir: P) -> anyhow::Result<ExtractSummary> {
+        self.extract_files_with(out_dir, &ExtractOptions::default())
+    }
+
+    /// Extracts the entries selected by `options.filter` into `out`, like
+    /// [`crate::MPKFileReader::extract_files_with`] does for MPK archives
+    pub fn extract_files_with<P: AsRef<Path>>(
+        &self,
+        out: P,
+        options: &ExtractOptions,
+    ) -> anyhow::Result<ExtractSummary> {
+        let filter = &options.filter;
+        let incremental = IncrementalState::load(out.as_ref(), options)?;
+
+        let selected: Vec<(usize, u64)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(index, entry)| filter.matches_location(*index, 0, entry.size as u64))
+            .map(|(index, entry)| (index, entry.size as u64))
+            .collect();
+        extract_parallel(
+            &self.path,
+            out.as_ref(),
+            self.entries.len(),
+            &selected,
+            options,
+            incremental.as_ref(),
+            |index| self.extract_entry(options, incremental.as_ref(), index),
+            |index| self.entry_name(index),
+        )
+    }
+
+    /// Searches the decoded entries selected by `filter` for `query`, like
+    /// [`crate::MPKFileReader::grep`] does for MPK archives
+    pub fn grep(&self, query: &GrepQuery, filter: &ExtractFilter) -> anyhow::Result<MPKGrepReport> {
+        let selected: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(index, entry)| filter.matches_location(*index, 0, entry.size as u64))
+            .map(|(index, _)| index)
+            .collect();
+        Ok(grep_parallel(
+            &selected,
+            query,
+            filter,
+            |index| {
+                let (data, name) = self.decode_entry(index)?;
+                Ok((name, data))
+            },
+            |index| self.entry_name(index),
+        ))
+    }
+
+    fn extract_entry(
+        &self,
+        options: &ExtractOptions,
+        incremental: Option<&IncrementalState>,
+        index: usize,
+    ) -> anyhow::Result<Extracted<'_>> {
+        let filter = &options.filter;
+        // Names from the name table are final unless a container hides a script name
+        let known_name = self.entries[index].name.as_ref().filter(|_| {
+            self.payload(index)
+                .ok()
+                .and_then(|payload| self.codecs.detect(payload))
+                .is_none_or(|codec| !codec.sniff_name())
+        });
+        if known_name.is_some_and(|name| !filter.matches_name(name)) {
+            return Ok(Extracted::Skipped);
+        }
+
+        let entry = &self.entries[index];
+        let unchanged = incremental
+            .zip(self.payload(index).ok())
+            .and_then(|(state, payload)| state.unchanged(&self.entry_name(index), payload, filter));
+        if let Some(mut record) = unchanged {
+            record.index = index;
+            record.offset = entry.offset as u64;
+            record.hash = Some(entry.hash);
+            return Ok(Extracted::Unchanged(record.path.clone(), Some(record)));
+        }
+
+        let (file_buffer, file_name) = self.decode_entry(index)?;
+        if !filter.matches_name(&file_name) || !filter.matches_content(&file_buffer) {
+            return Ok(Extracted::Skipped);
+        }
+        let record = options.records().then(|| {
+            let mut record = ManifestEntry::file(
+                index,
+                self.entry_name(index),
+                file_name.clone(),
+                0,
+                entry.offset as u64,
+                &self.container_name(index),
+                self.payload(index).unwrap_or_default(),
+                &file_buffer,
+            );
+            record.hash = Some(entry.hash);
+            record
+        });
+        if incremental.is_some_and(|state| state.is_written(&file_name, &file_buffer)) {
+            return Ok(Extracted::Unchanged(file_name, record));
+        }
+        Ok(Extracted::File(file_name, file_buffer, record))
+    }
+
+    fn payload(&self, index: usize) -> Result<&[u8], MPKError> {
+        let entry = &self.entries[index];
+        let start = entry.offset as usize;
+        start
+            .checked_add(entry.size as usize)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or_else(|| MPKError::EntryOutOfRange(self.entry_name(index)))
+    }
+
+    fn container_name(&self, index: usize) -> String {
+        let entry = &self.entries[index];
+        if entry.encryption != 0 {
+            return format!("encrypted ({})", entry.encryption);
+        }
+        match entry.compression {
+            NPKCompression::None => self
+                .payload(index)
+                .map(|payload| self.codecs.container_name(payload))
+                .unwrap_or("raw")
+                .to_string(),
+            NPKCompression::Zlib => Zlib.name().to_string(),
+            NPKCompression::Lz4 => "lz4".to_string(),
+            NPKCompression::Unknown(compression) => format!("unknown ({})", compression),
+        }
+    }
+
+    /// Undoes the compression flagged in the index and any Messiah container
+    /// below it, returns the decoded data and the name to extract it to
+    fn decode_entry(&self, index: usize) -> Result<(Cow<'_, [u8]>, String), MPKError> {
+        let entry = &self.entries[index];
+        let name = self.entry_name(index);
+        let payload = self.payload(index)?;
+        if entry.encryption != 0 {
+            return Err(MPKError::EncryptedEntry(name, entry.encryption));
+        }
+
+        let limit = self.codecs.max_decoded_size();
+        let container_error = |container, source| MPKError::InvalidContainer {
+            name: name.clone(),
+            container,
+            source,
+        };
+        let (file_buffer, alt_file_name) = match entry.compression {
+            NPKCompression::None => decode_payload(&self.codecs, &self.signatures, &name, payload)?,
+            NPKCompression::Zlib => {
+                let decompressed = Zlib
+                    .decode_limited(payload, limit)
+                    .map_err(|source| container_error("zlib", source))?;
+                self.decode_owned(&name, decompressed)?
+            }
+            NPKCompression::Lz4 => {
+                let decompressed = decompress_block(payload, entry.original_size as usize, limit)
+                    .map_err(|source| container_error("lz4", source))?;
+                self.decode_owned(&name, decompressed)?
+            }
+            NPKCompression::Unknown(compression) => {
+                return Err(MPKError::UnsupportedCompression(name, compression))
+            }
+        };
+
+        let file_name = match (alt_file_name, &entry.name) {
+            (Some(file_name), _) => file_name,
+            (None, Some(_)) => name,
+            (None, None) => sniff_file_name(&self.signatures, &name, &file_buffer),
+        };
+        Ok((file_buffer, file_name))
+    }
+
+    /// [`decode_payload`] for data that was already decompressed once
+    fn decode_owned(
+        &self,
+        name: &str,
+        decompressed: Vec<u8>,
+    ) -> Result<(Cow<'static, [u8]>, Option<String>), MPKError> {
+        if self.codecs.detect(&decompressed).is_none() {
+            return Ok((Cow::Owned(decompressed), None));
+        }
+        let (file_buffer, alt_file_name) =
+            decode_payload(&self.codecs, &self.signatures, name, &decompressed)?;
+        Ok((Cow::Owned(file_buffer.into_owned()), alt_file_name))
+    }
+}
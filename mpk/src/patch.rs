@@ -0,0 +1,350 @@
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    shard_path, MPKCompression, MPKError, MPKFileEntry, MPKFileEntryV1, MPKFileEntryV2,
+    MPKFileHeader, MPKFileReader, DEFAULT_MAX_SHARD_SIZE,
+};
+
+/// What [`MPKPatcher::replace_file`] did with a file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum MPKPatchAction {
+    /// Entries of the same name now point to the new payload
+    Replaced,
+    /// No entry had the name, a new one was appended to the index
+    Added,
+}
+
+/// What [`MPKPatcher::finish`] wrote
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MPKPatchSummary {
+    pub replaced: usize,
+    pub added: usize,
+    /// Shards payloads were appended to
+    pub shards: Vec<u32>,
+}
+
+/// Replaces and adds entries of an existing archive without rebuilding it.
+///
+/// New payloads are appended to the last shard, or to a new one once the last shard
+/// would grow past the maximum shard size. [`MPKPatcher::finish`] only rewrites the
+/// `.mpkinfo`, the payloads of replaced entries stay behind as gaps in the shards.
+/// The index from before the first patch is kept as `basename.mpkinfo.bak` and the
+/// shard sizes as `basename.mpkinfo.shards`, see [`MPKPatcher::undo`].
+#[derive(Debug)]
+pub struct MPKPatcher {
+    path: PathBuf,
+    header: MPKFileHeader,
+    files: Vec<MPKFileEntry>,
+    compression: MPKCompression,
+    max_shard_size: u64,
+    shard: Option<BufWriter<std::fs::File>>,
+    /// Shard payloads are appended to and its current size
+    file_number: u32,
+    shard_offset: u64,
+    /// Sizes of the shards before the first patch, `None` if an earlier patch
+    /// already recorded them
+    shard_sizes: Option<Vec<u64>>,
+    summary: MPKPatchSummary,
+}
+
+impl MPKPatcher {
+    /// Opens the `.mpkinfo` at `path` for patching, its shards have to lie next to it
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to read .mpkinfo file from {}", path.display()))?;
+        let (header, files) = MPKFileReader::read_index(&mut BufReader::new(file))?;
+
+        // Shards past the last one the index refers to may hold payloads of an undone
+        // patch, append after those as well
+        let mut file_number = files
+            .iter()
+            .filter(|file| !file.is_folder())
+            .map(|file| file.file_number())
+            .max()
+            .unwrap_or_default();
+        while shard_path(path, file_number + 1).exists() {
+            file_number += 1;
+        }
+        let shard = shard_path(path, file_number);
+        let shard_offset = match std::fs::metadata(&shard) {
+            Ok(metadata) => metadata.len(),
+            // Indices of folders alone never had a shard written
+            Err(err)
+                if err.kind() == std::io::ErrorKind::NotFound
+                    && files.iter().all(|file| file.is_folder()) =>
+            {
+                0
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(MPKError::MissingShard(shard).into())
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let shard_sizes = match Self::backup_path(path).exists() {
+            true => None,
+            false => Some(
+                (0..)
+                    .map_while(|file_number| std::fs::metadata(shard_path(path, file_number)).ok())
+                    .map(|metadata| metadata.len())
+                    .collect(),
+            ),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            header,
+            files,
+            compression: MPKCompression::None,
+            max_shard_size: DEFAULT_MAX_SHARD_SIZE,
+            shard: None,
+            file_number,
+            shard_offset,
+            shard_sizes,
+            summary: MPKPatchSummary::default(),
+        })
+    }
+
+    pub fn set_compression(&mut self, compression: MPKCompression) {
+        self.compression = compression;
+    }
+
+    /// Start a new shard once the last one would grow past `size` bytes
+    pub fn set_max_shard_size(&mut self, size: u64) {
+        self.max_shard_size = size.min(u32::MAX as u64);
+    }
+
+    /// Points every entry named `name` to `data`, or adds a new entry if there is none.
+    ///
+    /// Version 2 entries are matched by the hash in the `file_{number}_{hash}.{ext}` name
+    /// extraction gives them. Names a [`crate::NameDictionary`] resolved aren't hashed
+    /// back, any other name fails with [`MPKError::InvalidEntryName`].
+    pub fn replace_file(&mut self, name: &str, data: &[u8]) -> anyhow::Result<MPKPatchAction> {
+        let v2_name = match self.header.version {
+            2 => Some(
+                parse_v2_name(name).ok_or_else(|| MPKError::InvalidEntryName(name.to_string()))?,
+            ),
+            _ => None,
+        };
+        let matches: Vec<usize> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| match (file, v2_name) {
+                (MPKFileEntry::V1(file), _) => file.name() == name,
+                (MPKFileEntry::V2(file), Some((_, hash))) => file.hash == hash,
+                (MPKFileEntry::V2(_), None) => false,
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if let Some(&folder) = matches.iter().find(|index| self.files[**index].is_folder()) {
+            return Err(MPKError::EntryIsFolder(self.files[folder].name()).into());
+        }
+
+        let (file_number, offset, size) = self.append_payload(name, data)?;
+        if matches.is_empty() {
+            self.files.push(match v2_name {
+                Some((extension, hash)) => MPKFileEntry::V2(MPKFileEntryV2 {
+                    name: extension,
+                    offset,
+                    size,
+                    flags: file_number << 1,
+                    hash,
+                    file_number,
+                }),
+                None => MPKFileEntry::V1(MPKFileEntryV1 {
+                    name: name.as_bytes().to_vec(),
+                    offset,
+                    size,
+                    is_folder: false,
+                    file_number,
+                }),
+            });
+            self.summary.added += 1;
+            return Ok(MPKPatchAction::Added);
+        }
+
+        for index in matches {
+            match &mut self.files[index] {
+                MPKFileEntry::V1(file) => {
+                    file.offset = offset;
+                    file.size = size;
+                    file.file_number = file_number;
+                }
+                MPKFileEntry::V2(file) => {
+                    file.offset = offset;
+                    file.size = size;
+                    file.flags = file_number << 1;
+                    file.file_number = file_number;
+                }
+            }
+        }
+        self.summary.replaced += 1;
+        Ok(MPKPatchAction::Replaced)
+    }
+
+    /// Replaces or adds every file below `dir`, using the path relative to `dir` as
+    /// the entry name
+    pub fn replace_directory<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+    ) -> anyhow::Result<Vec<(String, MPKPatchAction)>> {
+        let mut actions = Vec::new();
+        for entry in WalkDir::new(&dir).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .strip_prefix(&dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let data =
+                std::fs::read(entry.path()).with_context(|| entry.path().display().to_string())?;
+            let action = self.replace_file(&name, &data)?;
+            actions.push((name, action));
+        }
+        Ok(actions)
+    }
+
+    /// Flushes the appended payloads and rewrites the `.mpkinfo`, after backing up
+    /// the original one and the shard sizes unless an earlier patch already did
+    pub fn finish(mut self) -> anyhow::Result<MPKPatchSummary> {
+        if let Some(mut shard) = self.shard.take() {
+            shard.flush()?;
+            shard.get_ref().sync_all()?;
+        }
+
+        let backup = Self::backup_path(&self.path);
+        if let Some(shard_sizes) = self.shard_sizes.as_ref().filter(|_| !backup.exists()) {
+            // Written before the backup, whoever finds the backup finds the sizes too
+            let sizes = Self::shard_sizes_path(&self.path);
+            std::fs::write(&sizes, serde_json::to_vec(shard_sizes)?)
+                .with_context(|| format!("Failed to write {}", sizes.display()))?;
+            std::fs::copy(&self.path, &backup)
+                .with_context(|| format!("Failed to back up {}", self.path.display()))?;
+        }
+
+        // Written next to the index first, a crash never leaves a half written index
+        let temp = self.path.with_extension("mpkinfo.tmp");
+        let file = std::fs::File::create(&temp)
+            .with_context(|| format!("Failed to create .mpkinfo file {}", temp.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.header.file_count = self.files.len() as u32;
+        self.header.write_header(&mut writer)?;
+        for file in &self.files {
+            file.write_to(&mut writer)?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        std::fs::rename(&temp, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+
+        Ok(self.summary)
+    }
+
+    /// Restores the `.mpkinfo` at `path` and its shards from before the first patch.
+    /// Appended payloads are cut off again and shards the patches added are deleted.
+    pub fn undo<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let backup = Self::backup_path(path);
+        std::fs::rename(&backup, path)
+            .with_context(|| format!("Failed to restore {}", backup.display()))?;
+
+        // Patches from before the sizes were recorded only get their index restored
+        let sizes_path = Self::shard_sizes_path(path);
+        let shard_sizes: Vec<u64> = match std::fs::read(&sizes_path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {}", sizes_path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        for file_number in 0.. {
+            let shard = shard_path(path, file_number);
+            if !shard.exists() {
+                break;
+            }
+            match shard_sizes.get(file_number as usize) {
+                Some(&size) => std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&shard)
+                    .and_then(|file| file.set_len(size)),
+                None => std::fs::remove_file(&shard),
+            }
+            .with_context(|| format!("Failed to restore shard {}", shard.display()))?;
+        }
+        std::fs::remove_file(&sizes_path)?;
+        Ok(())
+    }
+
+    /// Where the original index of the `.mpkinfo` at `path` is kept
+    pub fn backup_path(path: &Path) -> PathBuf {
+        path.with_extension("mpkinfo.bak")
+    }
+
+    /// Where the shard sizes from before the first patch of `path` are kept
+    fn shard_sizes_path(path: &Path) -> PathBuf {
+        path.with_extension("mpkinfo.shards")
+    }
+
+    fn append_payload(&mut self, name: &str, data: &[u8]) -> anyhow::Result<(u32, u32, u32)> {
+        let payload = self.compression.encode(data)?;
+        let size = payload.len() as u64;
+        if size > self.max_shard_size {
+            return Err(MPKError::EntryTooLarge(name.to_string()).into());
+        }
+
+        if self.shard_offset + size > self.max_shard_size {
+            if let Some(mut shard) = self.shard.take() {
+                shard.flush()?;
+            }
+            self.file_number += 1;
+            self.shard_offset = 0;
+        }
+
+        let shard = match &mut self.shard {
+            Some(shard) => shard,
+            None => {
+                let path = shard_path(&self.path, self.file_number);
+                let file = std::fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&path)
+                    .with_context(|| format!("Failed to open shard {}", path.display()))?;
+                self.summary.shards.push(self.file_number);
+                self.shard.insert(BufWriter::new(file))
+            }
+        };
+        shard.write_all(&payload)?;
+
+        let offset = self.shard_offset as u32;
+        self.shard_offset += size;
+        Ok((self.file_number, offset, size as u32))
+    }
+}
+
+/// Extension and hash of a version 2 entry extracted as `file_{number}_{hash}.{ext}`
+fn parse_v2_name(name: &str) -> Option<([u8; 3], u32)> {
+    let name = name.rsplit('/').next()?;
+    let (stem, extension) = name.split_once('.')?;
+    let hash = stem
+        .strip_prefix("file_")?
+        .split_once('_')?
+        .1
+        .parse()
+        .ok()?;
+    let mut buffer = [0; 3];
+    let extension = extension.as_bytes();
+    if extension.len() > buffer.len() {
+        return None;
+    }
+    buffer[..extension.len()].copy_from_slice(extension);
+    Some((buffer, hash))
+}
@@ -0,0 +1,167 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::helpers;
+
+/// First four bytes of every Texture2D resource
+pub(crate) const TEXTURE2D_MAGIC: u32 = 16908802;
+
+/// A Messiah file type recognised by its content, see [`SignatureRegistry`]
+pub trait FileSignature: Send + Sync {
+    /// Short name of the file type, e.g. `Texture2D`
+    fn name(&self) -> &'static str;
+
+    /// Extension extracted files of this type get, without the dot
+    fn extension(&self) -> &'static str;
+
+    /// Whether the decoded data in `buffer` is of this type
+    fn detect(&self, buffer: &[u8]) -> bool;
+}
+
+/// Set of known engine file types, tried before falling back to MIME sniffing when
+/// a container hides the original name. The first signature detecting a file wins.
+pub struct SignatureRegistry {
+    signatures: Vec<Box<dyn FileSignature>>,
+}
+
+impl SignatureRegistry {
+    /// A registry without any signatures, only MIME sniffing is left
+    pub fn empty() -> Self {
+        Self {
+            signatures: Vec::new(),
+        }
+    }
+
+    /// Adds a signature, it takes precedence over every signature registered before it
+    pub fn register(&mut self, signature: Box<dyn FileSignature>) {
+        self.signatures.insert(0, signature);
+    }
+
+    pub fn detect(&self, buffer: &[u8]) -> Option<&dyn FileSignature> {
+        self.signatures
+            .iter()
+            .find(|signature| signature.detect(buffer))
+            .map(|signature| signature.as_ref())
+    }
+
+    /// Extension of the detected file type, or of the sniffed MIME type. `None` if
+    /// nothing more specific than `application/octet-stream` was found
+    pub fn extension(&self, buffer: &[u8]) -> Option<&'static str> {
+        match self.detect(buffer) {
+            Some(signature) => Some(signature.extension()),
+            None => helpers::extension_from_mime(buffer),
+        }
+    }
+}
+
+impl Default for SignatureRegistry {
+    /// All engine file types identified so far
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(RepositorySignature));
+        registry.register(Box::new(PycSignature));
+        registry.register(Box::new(TextureSignature));
+        registry
+    }
+}
+
+impl std::fmt::Debug for SignatureRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.signatures.iter().map(|signature| signature.name()))
+            .finish()
+    }
+}
+
+/// Texture2D resource, what `messiah-texture` converts
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TextureSignature;
+
+impl FileSignature for TextureSignature {
+    fn name(&self) -> &'static str {
+        "Texture2D"
+    }
+
+    fn extension(&self) -> &'static str {
+        "tex"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        buffer.starts_with(&TEXTURE2D_MAGIC.to_le_bytes())
+    }
+}
+
+/// Compiled Python script, recognised by the magic of a known Python release
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PycSignature;
+
+impl FileSignature for PycSignature {
+    fn name(&self) -> &'static str {
+        "pyc"
+    }
+
+    fn extension(&self) -> &'static str {
+        "pyc"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        matches!(
+            helpers::detect_python_version_from_py_header(buffer),
+            Ok(Some(_))
+        )
+    }
+}
+
+/// Resource `.repository` listing the resources of a package, see
+/// `messiah_resources::Repository`.
+///
+/// There is no magic, the file starts with a version, two flags and the `;`
+/// separated resource type names followed by the folder paths. Both lists have to
+/// fit into the file and be non-empty, the type names have to look like identifiers.
+/// The version and flags aren't checked, their valid values aren't known.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RepositorySignature;
+
+impl FileSignature for RepositorySignature {
+    fn name(&self) -> &'static str {
+        "repository"
+    }
+
+    fn extension(&self) -> &'static str {
+        "repository"
+    }
+
+    fn detect(&self, buffer: &[u8]) -> bool {
+        let Some(types_size) = buffer.get(10..12).map(LittleEndian::read_u16) else {
+            return false;
+        };
+        let types_end = 12 + types_size as usize;
+        let Some(types) = buffer.get(12..types_end) else {
+            return false;
+        };
+        if types.is_empty()
+            || !types
+                .iter()
+                .all(|byte| byte.is_ascii_alphanumeric() || b";_".contains(byte))
+        {
+            return false;
+        }
+
+        let Some(paths_size) = buffer
+            .get(types_end..types_end + 2)
+            .map(LittleEndian::read_u16)
+        else {
+            return false;
+        };
+        let (paths_start, paths_size) = match paths_size {
+            0xFFFF => match buffer.get(types_end + 2..types_end + 6) {
+                Some(size) => (types_end + 6, LittleEndian::read_u32(size) as usize),
+                None => return false,
+            },
+            size => (types_end + 2, size as usize),
+        };
+        paths_start
+            .checked_add(paths_size)
+            .and_then(|paths_end| buffer.get(paths_start..paths_end))
+            .is_some_and(|paths| !paths.is_empty() && std::str::from_utf8(paths).is_ok())
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+use zip::write::SimpleFileOptions;
+
+use crate::MPKError;
+
+/// What extraction writes the entries to
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ExtractFormat {
+    /// A directory tree
+    #[default]
+    Directory,
+    /// An uncompressed tar archive
+    Tar,
+    /// A tar archive compressed with zstd
+    TarZstd,
+    /// A zip archive with deflated members
+    Zip,
+}
+
+impl ExtractFormat {
+    /// Picks the format from the extension of `path`, everything unknown is a directory
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".tar") {
+            ExtractFormat::Tar
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            ExtractFormat::TarZstd
+        } else if name.ends_with(".zip") {
+            ExtractFormat::Zip
+        } else {
+            ExtractFormat::Directory
+        }
+    }
+}
+
+/// Destination of an extraction, created by [`ExtractSink::create`]
+#[allow(clippy::large_enum_variant)] // created once per extraction
+pub(crate) enum ExtractSink {
+    Directory(PathBuf),
+    Tar(tar::Builder<BufWriter<File>>),
+    TarZstd(tar::Builder<zstd::Encoder<'static, BufWriter<File>>>),
+    Zip {
+        writer: zip::ZipWriter<BufWriter<File>>,
+        /// zip members can't be replaced, see [`ExtractSink::add_file`]
+        written: HashSet<String>,
+    },
+}
+
+impl ExtractSink {
+    pub(crate) fn create(path: &Path, format: ExtractFormat) -> anyhow::Result<Self> {
+        let archive = || -> anyhow::Result<BufWriter<File>> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create archive {}", path.display()))?;
+            Ok(BufWriter::new(file))
+        };
+        Ok(match format {
+            ExtractFormat::Directory => {
+                std::fs::create_dir_all(path)?;
+                ExtractSink::Directory(path.to_path_buf())
+            }
+            ExtractFormat::Tar => ExtractSink::Tar(tar::Builder::new(archive()?)),
+            ExtractFormat::TarZstd => {
+                ExtractSink::TarZstd(tar::Builder::new(zstd::Encoder::new(archive()?, 0)?))
+            }
+            ExtractFormat::Zip => ExtractSink::Zip {
+                writer: zip::ZipWriter::new(archive()?),
+                written: HashSet::new(),
+            },
+        })
+    }
+
+    /// Output directory if files are written to disk, these can be written in parallel
+    pub(crate) fn directory(&self) -> Option<&Path> {
+        match self {
+            ExtractSink::Directory(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Whether a file added twice ends up with the content of the second one, zip archives
+    /// can't replace members so the second one fails there
+    pub(crate) fn replaces_files(&self) -> bool {
+        !matches!(self, ExtractSink::Zip { .. })
+    }
+
+    pub(crate) fn add_folder(&mut self, name: &str) -> anyhow::Result<()> {
+        checked_output_path(name)?;
+        match self {
+            ExtractSink::Directory(path) => {
+                let path = path.join(name);
+                std::fs::create_dir_all(&path).context(path.display().to_string())
+            }
+            ExtractSink::Tar(builder) => append_tar(builder, name, None),
+            ExtractSink::TarZstd(builder) => append_tar(builder, name, None),
+            ExtractSink::Zip { writer, .. } => {
+                writer.add_directory(name, SimpleFileOptions::default())?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds a file, archives get members in the order they are added.
+    ///
+    /// A later tar member replaces an earlier one with the same path when unpacking,
+    /// just like extracting to a directory does. zip archives can't hold both, every
+    /// later one fails, see [`ExtractSink::replaces_files`].
+    pub(crate) fn add_file(&mut self, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        checked_output_path(name)?;
+        match self {
+            ExtractSink::Directory(path) => write_file(path, name, data),
+            ExtractSink::Tar(builder) => append_tar(builder, name, Some(data)),
+            ExtractSink::TarZstd(builder) => append_tar(builder, name, Some(data)),
+            ExtractSink::Zip { writer, written } => {
+                if !written.insert(name.to_string()) {
+                    anyhow::bail!("{} is already part of the zip archive", name);
+                }
+                let options = SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(data.len() as u64 >= u32::MAX as u64);
+                writer.start_file(name, options)?;
+                writer.write_all(data)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the end of the archive
+    pub(crate) fn finish(self) -> anyhow::Result<()> {
+        match self {
+            ExtractSink::Directory(_) => {}
+            ExtractSink::Tar(builder) => builder.into_inner()?.flush()?,
+            ExtractSink::TarZstd(builder) => builder.into_inner()?.finish()?.flush()?,
+            ExtractSink::Zip { writer, .. } => writer.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Entry names come from the archive, so they may not climb out of the output with
+/// `..` or replace it with an absolute path
+pub(crate) fn checked_output_path(name: &str) -> Result<&Path, MPKError> {
+    let path = Path::new(name);
+    let escapes = path.components().any(|component| {
+        matches!(
+            component,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+    match escapes {
+        true => Err(MPKError::UnsafePath(name.to_string())),
+        false => Ok(path),
+    }
+}
+
+/// Writes the output `name` below `out_dir`
+pub(crate) fn write_file(out_dir: &Path, name: &str, data: &[u8]) -> anyhow::Result<()> {
+    let path = out_dir.join(checked_output_path(name)?);
+    std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| std::fs::write(&path, data))
+        .context(path.display().to_string())
+}
+
+/// Appends a file, or a folder without `data`. Timestamps are left at zero so
+/// extracting the same archive twice gives the same bytes
+fn append_tar<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    match data {
+        Some(data) => {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+        }
+        None => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+        }
+    }
+    header.set_mtime(0);
+    builder
+        .append_data(&mut header, name, data.unwrap_or_default())
+        .context(name.to_string())
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use memmap2::{Mmap, MmapOptions};
+
+use crate::{shard_path, MPKError};
+
+/// Where the shards of an archive are read from
+#[derive(Debug)]
+pub(crate) enum ShardSource {
+    /// `basename{n}.mpk` next to the `.mpkinfo`
+    Directory,
+    /// Members of a zip package like an APK or OBB, next to the `.mpkinfo` member
+    Package {
+        path: PathBuf,
+        /// Members of the shards the index refers to, by file number
+        shards: HashMap<u32, PackageMember>,
+    },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum PackageMember {
+    /// Stored without compression, read in place
+    Stored { start: u64, size: u64 },
+    /// Would have to be inflated first, which we don't do for shards
+    Compressed,
+}
+
+impl ShardSource {
+    /// Opens the shard with the given `file_number` of the `.mpkinfo` at `info_path`
+    pub(crate) fn open_shard(&self, info_path: &Path, file_number: u32) -> Result<Shard, MPKError> {
+        let path = shard_path(info_path, file_number);
+        let (file, start, size) = match self {
+            ShardSource::Directory => {
+                let file = File::open(&path).map_err(|err| match err.kind() {
+                    ErrorKind::NotFound => MPKError::MissingShard(path),
+                    _ => err.into(),
+                })?;
+                let size = file.metadata()?.len();
+                (file, 0, size)
+            }
+            ShardSource::Package {
+                path: package,
+                shards,
+            } => match shards.get(&file_number) {
+                Some(PackageMember::Stored { start, size }) => {
+                    (File::open(package)?, *start, *size)
+                }
+                Some(PackageMember::Compressed) => return Err(MPKError::CompressedShard(path)),
+                None => return Err(MPKError::MissingShard(path)),
+            },
+        };
+        Ok(Shard { file, start, size })
+    }
+}
+
+/// An opened shard, the whole `.mpk` on disk or the data of a stored member of a package
+#[derive(Debug)]
+pub(crate) struct Shard {
+    file: File,
+    /// Where the shard starts in `file`
+    start: u64,
+    size: u64,
+}
+
+impl Shard {
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads `size` bytes at `offset`, `None` if they lie outside of the shard
+    pub(crate) fn read_at(&mut self, offset: u64, size: u64) -> std::io::Result<Option<Vec<u8>>> {
+        if offset.checked_add(size).is_none_or(|end| end > self.size) {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(self.start + offset))?;
+        let mut buffer = vec![0; size as usize];
+        self.file.read_exact(&mut buffer)?;
+        Ok(Some(buffer))
+    }
+
+    /// Maps the shard into memory
+    pub(crate) fn map(&self) -> std::io::Result<Mmap> {
+        // SAFETY: shards are only ever read, the map is invalid if another process
+        // truncates one while we extract, just like it is for every other reader
+        unsafe {
+            MmapOptions::new()
+                .offset(self.start)
+                .len(self.size as usize)
+                .map(&self.file)
+        }
+    }
+}
+
+/// Splits a path running through a package, like `game.apk/assets/res.mpkinfo`, into
+/// the package on disk and the member name. `None` if `path` doesn't pass a file.
+pub(crate) fn split_package_path(path: &Path) -> Option<(&Path, String)> {
+    let package = path
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_file())?;
+    Some((package, member_name(path.strip_prefix(package).ok()?)))
+}
+
+/// Opens the zip package at `path`
+pub(crate) fn open_package(path: &Path) -> anyhow::Result<zip::ZipArchive<File>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to read package {}", path.display()))?;
+    zip::ZipArchive::new(file).with_context(|| format!("{} is not a zip package", path.display()))
+}
+
+impl ShardSource {
+    /// Locates the members of the shards in `file_numbers` next to `info_member`
+    pub(crate) fn package(
+        path: &Path,
+        archive: &mut zip::ZipArchive<File>,
+        info_member: &str,
+        file_numbers: impl IntoIterator<Item = u32>,
+    ) -> anyhow::Result<Self> {
+        let mut shards = HashMap::new();
+        for file_number in file_numbers {
+            let name = member_name(&shard_path(Path::new(info_member), file_number));
+            let Some(index) = archive.index_for_name(&name) else {
+                continue;
+            };
+            let member = archive.by_index_raw(index)?;
+            let member = match member.compression() {
+                zip::CompressionMethod::Stored if !member.encrypted() => PackageMember::Stored {
+                    start: member.data_start(),
+                    size: member.size(),
+                },
+                _ => PackageMember::Compressed,
+            };
+            shards.insert(file_number, member);
+        }
+        Ok(ShardSource::Package {
+            path: path.to_path_buf(),
+            shards,
+        })
+    }
+}
+
+/// Zip member name of a relative path, zip always separates with `/`
+fn member_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use messiah_codec::Integrity;
+use serde::Serialize;
+
+use crate::MPKFileReader;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum MPKVerifyStatus {
+    /// Decoded fine and the container checksum matched
+    Verified,
+    /// Decoded fine, there is nothing else to check for this container
+    Unchecked,
+    /// The payload could not be read or decoded
+    Corrupt(String),
+}
+
+impl MPKVerifyStatus {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, MPKVerifyStatus::Verified | MPKVerifyStatus::Unchecked)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MPKVerifyResult {
+    pub index: usize,
+    pub name: String,
+    pub container: String,
+    #[serde(flatten)]
+    pub status: MPKVerifyStatus,
+}
+
+impl MPKFileReader {
+    /// Checks every file entry of the archive, without writing anything.
+    ///
+    /// Entries in a missing shard, pointing past the end of their shard or failing to
+    /// decode are reported as [`MPKVerifyStatus::Corrupt`].
+    pub fn verify_entries(&self) -> anyhow::Result<Vec<MPKVerifyResult>> {
+        let mut mpk_map = HashMap::new();
+        let mut results = Vec::new();
+        for (index, file) in self.files.iter().enumerate() {
+            if file.is_folder() {
+                continue;
+            }
+
+            let file_number = file.file_number();
+            let read = match mpk_map
+                .entry(file_number)
+                .or_insert_with(|| self.open_shard(file_number))
+            {
+                Ok(mpk_file) => self.read_entry(mpk_file, file).map_err(|e| e.to_string()),
+                // Every entry of a missing shard is reported, not just the first one
+                Err(e) => Err(e.to_string()),
+            };
+
+            let (container, status) = match read {
+                Ok(file_buffer) => match self.codecs.detect(&file_buffer) {
+                    Some(codec) => {
+                        let limit = self.codecs.max_decoded_size();
+                        let status = match codec.verify_limited(&file_buffer, limit) {
+                            Ok(Integrity::Verified) => MPKVerifyStatus::Verified,
+                            Ok(Integrity::Unchecked) => MPKVerifyStatus::Unchecked,
+                            Err(e) => MPKVerifyStatus::Corrupt(e.to_string()),
+                        };
+                        (codec.name().to_string(), status)
+                    }
+                    None => ("raw".to_string(), MPKVerifyStatus::Unchecked),
+                },
+                Err(e) => ("unknown".to_string(), MPKVerifyStatus::Corrupt(e)),
+            };
+
+            results.push(MPKVerifyResult {
+                index,
+                name: self.entry_name(file),
+                container,
+                status,
+            });
+        }
+        Ok(results)
+    }
+}
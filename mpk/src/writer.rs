@@ -0,0 +1,335 @@
+use std::io::{BufWriter, Write};
+
+use anyhow::Context;
+use walkdir::WalkDir;
+
+use crate::{shard_path, MPKError, MPKFileEntry, MPKFileEntryV1, MPKFileEntryV2, MPKFileHeader};
+
+/// Largest shard we start by default, offsets in the index are only 32 bits wide.
+pub const DEFAULT_MAX_SHARD_SIZE: u64 = 1 << 30;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum MPKCompression {
+    /// Store the payload as is
+    #[default]
+    None,
+    /// Store the payload as a `ZZZ4` lz4 block with the uncompressed size prepended
+    Lz4,
+    /// Store the payload as a `\xE2\x06` mangled zlib stream, the way scripts are stored
+    MangledZlib,
+}
+
+impl MPKCompression {
+    /// The payload stored for `data`
+    pub(crate) fn encode(self, data: &[u8]) -> anyhow::Result<std::borrow::Cow<'_, [u8]>> {
+        Ok(match self {
+            MPKCompression::None => std::borrow::Cow::Borrowed(data),
+            MPKCompression::Lz4 => {
+                let mut payload = b"ZZZ4".to_vec();
+                payload.extend(lz4_flex::compress_prepend_size(data));
+                std::borrow::Cow::Owned(payload)
+            }
+            MPKCompression::MangledZlib => {
+                std::borrow::Cow::Owned(messiah_codec::MangledZlib.encode(data)?)
+            }
+        })
+    }
+}
+
+/// Builds a `.mpkinfo` index and its `.mpk` shards.
+///
+/// Payloads are streamed into `basename.mpk`, `basename1.mpk`, ... as they are added,
+/// the index itself is only written out by [`MPKFileWriter::finish`].
+///
+/// Version 2 indices store a name hash instead of the path. The engine's hash function
+/// isn't known, see [`crate::NameHash`], so their entries can only be added with an
+/// explicit hash through [`MPKFileWriter::add_file_v2`].
+#[derive(Debug)]
+pub struct MPKFileWriter {
+    path: std::path::PathBuf,
+    version: u32,
+    compression: MPKCompression,
+    max_shard_size: u64,
+    files: Vec<MPKFileEntry>,
+    shard: Option<BufWriter<std::fs::File>>,
+    file_number: u32,
+    shard_offset: u64,
+}
+
+impl MPKFileWriter {
+    /// Creates a writer for a version 1 or version 2 `.mpkinfo` at `path`
+    pub fn new<P: AsRef<std::path::Path>>(path: P, version: u32) -> anyhow::Result<Self> {
+        if version != 1 && version != 2 {
+            return Err(MPKError::UnsupportedVersion(version).into());
+        }
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            version,
+            compression: MPKCompression::None,
+            max_shard_size: DEFAULT_MAX_SHARD_SIZE,
+            files: Vec::new(),
+            shard: None,
+            file_number: 0,
+            shard_offset: 0,
+        })
+    }
+
+    pub fn set_compression(&mut self, compression: MPKCompression) {
+        self.compression = compression;
+    }
+
+    /// Start a new shard once the current one would grow past `size` bytes
+    pub fn set_max_shard_size(&mut self, size: u64) {
+        self.max_shard_size = size.min(u32::MAX as u64);
+    }
+
+    /// Adds a folder record, version 2 indices have no folder names so they are skipped there
+    pub fn add_folder(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.version == 1 {
+            self.files.push(MPKFileEntry::V1(MPKFileEntryV1 {
+                name: name.as_bytes().to_vec(),
+                offset: 0,
+                size: 0,
+                is_folder: true,
+                file_number: 0,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Adds a file to a version 1 index
+    pub fn add_file(&mut self, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        if self.version != 1 {
+            return Err(MPKError::UnsupportedVersion(self.version).into());
+        }
+
+        let (file_number, offset, size) = self.write_payload(name, data)?;
+        self.files.push(MPKFileEntry::V1(MPKFileEntryV1 {
+            name: name.as_bytes().to_vec(),
+            offset,
+            size,
+            is_folder: false,
+            file_number,
+        }));
+        Ok(())
+    }
+
+    /// Adds a file to a version 2 index with an explicit extension and name hash
+    pub fn add_file_v2(
+        &mut self,
+        extension: [u8; 3],
+        hash: u32,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        if self.version != 2 {
+            return Err(MPKError::UnsupportedVersion(self.version).into());
+        }
+
+        let name = format!("{}.{}", hash, String::from_utf8_lossy(&extension));
+        let (file_number, offset, size) = self.write_payload(&name, data)?;
+        self.files.push(MPKFileEntry::V2(MPKFileEntryV2 {
+            name: extension,
+            offset,
+            size,
+            flags: file_number << 1,
+            hash,
+            file_number,
+        }));
+        Ok(())
+    }
+
+    /// Adds every file below `dir` to a version 1 index, using the path relative to `dir`
+    /// as the entry name
+    pub fn add_directory<P: AsRef<std::path::Path>>(&mut self, dir: P) -> anyhow::Result<()> {
+        if self.version != 1 {
+            return Err(MPKError::UnsupportedVersion(self.version).into());
+        }
+        for entry in WalkDir::new(&dir).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+            let name = entry
+                .path()
+                .strip_prefix(&dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            if entry.file_type().is_dir() {
+                self.add_folder(&name)?;
+            } else {
+                let data = std::fs::read(entry.path())
+                    .with_context(|| entry.path().display().to_string())?;
+                self.add_file(&name, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the last shard and writes the `.mpkinfo` index
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if let Some(mut shard) = self.shard.take() {
+            shard.flush()?;
+        }
+
+        let header = MPKFileHeader {
+            version: self.version,
+            file_count: self.files.len() as u32,
+        };
+        let file = std::fs::File::create(&self.path)
+            .with_context(|| format!("Failed to create .mpkinfo file {}", self.path.display()))?;
+        let mut writer = BufWriter::new(file);
+        header.write_header(&mut writer)?;
+        for file in &self.files {
+            file.write_to(&mut writer)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn write_payload(&mut self, name: &str, data: &[u8]) -> anyhow::Result<(u32, u32, u32)> {
+        let payload = self.compression.encode(data)?;
+        let size = payload.len() as u64;
+        if size > self.max_shard_size {
+            return Err(MPKError::EntryTooLarge(name.to_string()).into());
+        }
+
+        if self.shard.is_some() && self.shard_offset + size > self.max_shard_size {
+            if let Some(mut shard) = self.shard.take() {
+                shard.flush()?;
+            }
+            self.file_number += 1;
+            self.shard_offset = 0;
+        }
+
+        let shard = match &mut self.shard {
+            Some(shard) => shard,
+            None => {
+                let path = shard_path(&self.path, self.file_number);
+                let file = std::fs::File::create(&path)
+                    .with_context(|| format!("Failed to create shard {}", path.display()))?;
+                self.shard.insert(BufWriter::new(file))
+            }
+        };
+        shard.write_all(&payload)?;
+
+        let offset = self.shard_offset as u32;
+        self.shard_offset += size;
+        Ok((self.file_number, offset, size as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+
+    use super::*;
+    use crate::MPKFileReader;
+
+    /// Reads the index back entry by entry the way [`MPKFileReader`] does
+    fn read_index(path: &std::path::Path) -> Vec<MPKFileEntry> {
+        let mut reader = BufReader::new(File::open(path).unwrap());
+        let header = MPKFileHeader::read_header(&mut reader).unwrap();
+        let files = (0..header.file_count)
+            .map(|_| MPKFileEntry::read_from(&mut reader, header.version).unwrap())
+            .collect();
+        assert_eq!(reader.read(&mut [0]).unwrap(), 0);
+        files
+    }
+
+    fn payload(index: usize) -> Vec<u8> {
+        (0..index * 700).map(|byte| (byte % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.mpkinfo");
+        let mut writer = MPKFileWriter::new(&path, 1).unwrap();
+        writer.set_max_shard_size(4096);
+        writer.add_folder("data").unwrap();
+        let mut expected = vec![("data".to_string(), 0, 0, vec![])];
+        let mut shard_offset = [0u32; 8];
+        for (index, compression) in [
+            MPKCompression::None,
+            MPKCompression::Lz4,
+            MPKCompression::MangledZlib,
+            MPKCompression::None,
+            MPKCompression::None,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let name = format!("data/{}.bin", index);
+            let data = payload(index);
+            writer.set_compression(compression);
+            writer.add_file(&name, &data).unwrap();
+            let size = compression.encode(&data).unwrap().len() as u32;
+            let file_number = match writer.files.last().unwrap() {
+                MPKFileEntry::V1(file) => file.file_number,
+                MPKFileEntry::V2(_) => unreachable!(),
+            };
+            expected.push((name, file_number, shard_offset[file_number as usize], data));
+            shard_offset[file_number as usize] += size;
+        }
+        writer.finish().unwrap();
+        assert!(shard_path(&path, 1).exists());
+
+        let files = read_index(&path);
+        assert_eq!(files.len(), expected.len());
+        assert!(files[0].is_folder());
+        let reader = MPKFileReader::new(&path).unwrap();
+        for (index, (file, (name, file_number, offset, data))) in
+            files.iter().zip(expected).enumerate()
+        {
+            assert_eq!(file.version(), 1);
+            assert_eq!(file.name(), name);
+            assert_eq!(file.file_number(), file_number);
+            assert_eq!(file.offset(), offset);
+            if !file.is_folder() {
+                let mut decoded = Vec::new();
+                reader
+                    .open_entry(index)
+                    .unwrap()
+                    .read_to_end(&mut decoded)
+                    .unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_v2() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.mpkinfo");
+        let mut writer = MPKFileWriter::new(&path, 2).unwrap();
+        assert!(writer.add_file("data/0.bin", b"data").is_err());
+        writer.set_compression(MPKCompression::Lz4);
+        let mut expected = Vec::new();
+        let mut offset = 0;
+        for index in 0..4 {
+            let data = payload(index);
+            let hash = 0x1000_0000 + index as u32;
+            writer.add_file_v2(*b"bin", hash, &data).unwrap();
+            expected.push((format!("file_0_{}.bin", hash), hash, offset, data));
+            offset += MPKCompression::Lz4.encode(&payload(index)).unwrap().len() as u32;
+        }
+        writer.finish().unwrap();
+
+        let files = read_index(&path);
+        assert_eq!(files.len(), expected.len());
+        let reader = MPKFileReader::new(&path).unwrap();
+        for (index, (file, (name, hash, offset, data))) in files.iter().zip(expected).enumerate() {
+            assert_eq!(file.version(), 2);
+            assert_eq!(file.name(), name);
+            assert_eq!(file.hash(), Some(hash));
+            assert_eq!(file.file_number(), 0);
+            assert_eq!(file.offset(), offset);
+            let mut decoded = Vec::new();
+            reader
+                .open_entry(index)
+                .unwrap()
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, data);
+        }
+    }
+}
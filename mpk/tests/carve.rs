@@ -0,0 +1,107 @@
+use messiah_mpk::{CarveSignature, MPKCarver, MPKCompression, MPKFileReader};
+
+mod common;
+use common::{payload, write_archive};
+
+const TEXT: &[u8] = b"plain text no signature matches, plain text no signature matches";
+
+/// Offset, size, signature and whether the boundaries are exact
+type Carved = (u32, u32, Option<CarveSignature>, bool);
+
+/// Carves the shard of an archive holding `entries`, next to the offsets and sizes
+/// of the real index
+fn carve(entries: &[(&str, MPKCompression, &[u8])]) -> (Vec<Carved>, Vec<(u32, u32)>) {
+    let dir = tempfile::tempdir().unwrap();
+    let reader = MPKFileReader::new(write_archive(dir.path(), entries)).unwrap();
+    let written = reader
+        .entries()
+        .map(|entry| (entry.offset(), entry.size()))
+        .collect();
+
+    let carve = MPKCarver::new().carve(dir.path().join("test.mpk")).unwrap();
+    let carved = carve
+        .entries()
+        .iter()
+        .map(|entry| (entry.offset, entry.size, entry.signature, entry.exact))
+        .collect();
+    (carved, written)
+}
+
+#[test]
+fn recovers_written_entries() {
+    let (lz4, mangled) = (payload(10), payload(12));
+    let (carved, written) = carve(&[
+        ("a.txt", MPKCompression::None, TEXT),
+        ("b.bin", MPKCompression::Lz4, &lz4),
+        ("c.bin", MPKCompression::MangledZlib, &mangled),
+        ("d.txt", MPKCompression::None, TEXT),
+        ("e.bin", MPKCompression::MangledZlib, &lz4),
+        ("f.bin", MPKCompression::Lz4, &mangled),
+    ]);
+
+    let boundaries: Vec<_> = carved
+        .iter()
+        .map(|(offset, size, _, _)| (*offset, *size))
+        .collect();
+    assert_eq!(boundaries, written);
+    let kinds: Vec<_> = carved
+        .iter()
+        .map(|(_, _, signature, exact)| (*signature, *exact))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (None, false),
+            (Some(CarveSignature::Lz4), true),
+            (Some(CarveSignature::MangledZlib), true),
+            (None, false),
+            (Some(CarveSignature::MangledZlib), true),
+            (Some(CarveSignature::Lz4), true),
+        ]
+    );
+}
+
+#[test]
+fn raw_data_behind_lz4_is_absorbed() {
+    let lz4 = payload(10);
+    let (carved, written) = carve(&[
+        ("a.bin", MPKCompression::Lz4, &lz4),
+        ("b.txt", MPKCompression::None, TEXT),
+        ("c.bin", MPKCompression::MangledZlib, &lz4),
+    ]);
+
+    // The lz4 block doesn't decode with the text behind it, so it runs up to the
+    // next signature, the zlib stream knows its own end
+    let (a, b, c) = (written[0], written[1], written[2]);
+    assert_eq!(
+        carved,
+        [
+            (a.0, a.1 + b.1, Some(CarveSignature::Lz4), false),
+            (c.0, c.1, Some(CarveSignature::MangledZlib), true),
+        ]
+    );
+}
+
+#[test]
+fn carved_index_reads_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = payload(10);
+    write_archive(
+        dir.path(),
+        &[
+            ("a.bin", MPKCompression::MangledZlib, &data),
+            ("b.bin", MPKCompression::Lz4, &data),
+        ],
+    );
+    std::fs::remove_file(dir.path().join("test.mpkinfo")).unwrap();
+
+    let carve = MPKCarver::new().carve(dir.path().join("test.mpk")).unwrap();
+    carve.write_index().unwrap();
+    // An index that exists is never replaced
+    assert!(carve.write_index().is_err());
+
+    let reader = MPKFileReader::new(carve.info_path()).unwrap();
+    for index in 0..2 {
+        assert_eq!(reader.open_entry(index).unwrap().into_inner(), data);
+    }
+}
@@ -0,0 +1,132 @@
+//! Archives for the integration tests, built with [`MPKFileWriter`] in a temporary directory
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use messiah_mpk::{MPKCompression, MPKError, MPKFileReader, MPKFileWriter};
+
+/// Shards of the fixtures are kept small so a few entries already span several
+pub const MAX_SHARD_SIZE: u64 = 4096;
+
+/// Writes a version 1 archive holding `entries` to `dir/test.mpkinfo` and returns its path
+pub fn write_archive(dir: &Path, entries: &[(&str, MPKCompression, &[u8])]) -> PathBuf {
+    let path = dir.join("test.mpkinfo");
+    let mut writer = MPKFileWriter::new(&path, 1).unwrap();
+    writer.set_max_shard_size(MAX_SHARD_SIZE);
+    for (name, compression, data) in entries {
+        writer.set_compression(*compression);
+        writer.add_file(name, data).unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+/// Replaces the index at `path` with a version 1 index of `entries`, given by name,
+/// shard, offset and size, to lay out entries the writer never would
+pub fn write_index(path: &Path, entries: &[(&str, u32, u32, u32)]) {
+    let mut index = 1u32.to_le_bytes().to_vec();
+    index.extend((entries.len() as u32).to_le_bytes());
+    for (name, shard, offset, size) in entries {
+        index.extend((name.len() as u16).to_le_bytes());
+        index.extend(name.as_bytes());
+        for word in [*offset, *size, shard << 1] {
+            index.extend(word.to_le_bytes());
+        }
+    }
+    std::fs::write(path, index).unwrap();
+}
+
+/// Packs the files in `members` into a zip package at `path`, under the given member
+/// names, deflating those flagged and storing the rest
+pub fn write_package(path: &Path, members: &[(&str, &Path, bool)]) {
+    use zip::write::SimpleFileOptions;
+    use zip::CompressionMethod;
+
+    let mut package = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    for (name, file, deflated) in members {
+        let method = match deflated {
+            true => CompressionMethod::Deflated,
+            false => CompressionMethod::Stored,
+        };
+        let options = SimpleFileOptions::default().compression_method(method);
+        package.start_file(*name, options).unwrap();
+        std::io::Write::write_all(&mut package, &std::fs::read(file).unwrap()).unwrap();
+    }
+    package.finish().unwrap();
+}
+
+/// A version 1 archive with folder records for `Script` and the empty `Empty`,
+/// `res/ui` only implied by the files inside it
+pub fn write_tree(dir: &Path) -> PathBuf {
+    let path = dir.join("test.mpkinfo");
+    let mut writer = MPKFileWriter::new(&path, 1).unwrap();
+    writer.set_max_shard_size(MAX_SHARD_SIZE);
+    writer.add_folder("Script").unwrap();
+    writer.add_file("Script/a.py", b"print('a')").unwrap();
+    writer.add_file("res/ui/button.png", b"png").unwrap();
+    writer.add_folder("Empty").unwrap();
+    writer.add_file("readme.txt", b"read me").unwrap();
+    writer.add_file("res\\ui\\icon.png", b"icon").unwrap();
+    writer.set_compression(MPKCompression::MangledZlib);
+    writer.add_file("Script/b.py", b"print('b')").unwrap();
+    writer.set_compression(MPKCompression::None);
+    writer
+        .add_file("res/broken.bin", b"CCCCZSTD\x04\x00\x00\x00data")
+        .unwrap();
+    writer.finish().unwrap();
+    path
+}
+
+/// [`write_archive`] with the entries stored raw, as lz4 and as mangled zlib in turn
+pub fn build(dir: &Path, entries: &[(&str, &[u8])]) -> MPKFileReader {
+    let entries: Vec<_> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, (name, data))| (*name, compression(index), *data))
+        .collect();
+    MPKFileReader::new(write_archive(dir, &entries)).unwrap()
+}
+
+/// How [`build`] stores entry `index`
+pub fn compression(index: usize) -> MPKCompression {
+    match index % 3 {
+        0 => MPKCompression::None,
+        1 => MPKCompression::Lz4,
+        _ => MPKCompression::MangledZlib,
+    }
+}
+
+/// Data for entry `index`, growing with the index
+pub fn payload(index: usize) -> Vec<u8> {
+    (0..index * 60).map(|byte| (byte * 7 % 251) as u8).collect()
+}
+
+/// The [`MPKError`] behind `err`, whatever context was added on the way
+pub fn mpk_error(err: &anyhow::Error) -> &MPKError {
+    err.chain()
+        .find_map(|source| source.downcast_ref::<MPKError>())
+        .unwrap_or_else(|| panic!("not an MPKError: {:#}", err))
+}
+
+/// Every file below `dir` with its contents, by path relative to `dir`
+pub fn read_tree(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<(String, Vec<u8>)>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(root, &path, files);
+            } else {
+                let name = path
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                files.push((name, std::fs::read(&path).unwrap()));
+            }
+        }
+    }
+    let mut files = Vec::new();
+    walk(dir, dir, &mut files);
+    files.sort();
+    files
+}
@@ -0,0 +1,15 @@
+"""Sample module used by the marshal tests"""
+
+GREETING = "hello"
+
+
+def greet(name, greeting=GREETING):
+    return "%s, %s" % (greeting, name)
+
+
+class Greeter(object):
+    def __init__(self, name):
+        self.name = name
+
+    def greet(self):
+        return greet(self.name)
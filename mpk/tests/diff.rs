@@ -0,0 +1,87 @@
+use messiah_mpk::{MPKCompression, MPKDiff, MPKFileReader};
+
+mod common;
+use common::write_archive;
+
+/// Looks like a lz4 container but doesn't decode
+const BROKEN: &[u8] = b"ZZZ4\x10\x00\x00\x00broken";
+
+fn archives() -> (tempfile::TempDir, MPKFileReader, MPKFileReader) {
+    let dir = tempfile::tempdir().unwrap();
+    let data = vec![b'x'; 1000];
+    let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+    std::fs::create_dir_all(&old).unwrap();
+    std::fs::create_dir_all(&new).unwrap();
+    let old = write_archive(
+        &old,
+        &[
+            ("same.bin", MPKCompression::None, b"same"),
+            ("recompressed.bin", MPKCompression::None, &data),
+            ("edited.bin", MPKCompression::None, b"before"),
+            ("removed.bin", MPKCompression::None, b"removed"),
+            ("broken.bin", MPKCompression::None, BROKEN),
+        ],
+    );
+    let new = write_archive(
+        &new,
+        &[
+            ("same.bin", MPKCompression::None, b"same"),
+            ("recompressed.bin", MPKCompression::Lz4, &data),
+            ("edited.bin", MPKCompression::None, b"after!"),
+            ("added.bin", MPKCompression::None, b"added"),
+            ("broken.bin", MPKCompression::None, BROKEN),
+        ],
+    );
+    let old = MPKFileReader::new(old).unwrap();
+    let new = MPKFileReader::new(new).unwrap();
+    (dir, old, new)
+}
+
+fn changed(diff: &MPKDiff) -> Vec<&str> {
+    diff.changed
+        .iter()
+        .map(|change| change.name.as_str())
+        .collect()
+}
+
+#[test]
+fn stored_payloads() {
+    let (_dir, old, new) = archives();
+    let diff = MPKDiff::compute(&old, &new, false).unwrap();
+    assert_eq!(diff.added.keys().collect::<Vec<_>>(), ["added.bin"]);
+    assert_eq!(diff.removed.keys().collect::<Vec<_>>(), ["removed.bin"]);
+    assert_eq!(changed(&diff), ["edited.bin", "recompressed.bin"]);
+    assert!(diff.failed.is_empty());
+    assert_eq!(diff.added["added.bin"].index, 3);
+    let mut indices = diff.new_indices();
+    indices.sort();
+    assert_eq!(indices, [1, 2, 3]);
+}
+
+#[test]
+fn content() {
+    let (_dir, old, new) = archives();
+    let diff = MPKDiff::compute(&old, &new, true).unwrap();
+    assert_eq!(changed(&diff), ["edited.bin"]);
+    assert_eq!(diff.changed[0].new.size, 6);
+    let failed: Vec<_> = diff
+        .failed
+        .iter()
+        .map(|change| change.name.as_str())
+        .collect();
+    assert_eq!(failed, ["broken.bin"]);
+    assert!(diff.failed[0].old.error.is_some() && diff.failed[0].old.digest.is_none());
+}
+
+#[test]
+fn failures_are_differences() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_archive(dir.path(), &[("broken.bin", MPKCompression::None, BROKEN)]);
+    let reader = MPKFileReader::new(path).unwrap();
+    assert!(MPKDiff::compute(&reader, &reader, false)
+        .unwrap()
+        .is_empty());
+    let diff = MPKDiff::compute(&reader, &reader, true).unwrap();
+    assert_eq!(diff.failed.len(), 1);
+    assert!(!diff.is_empty());
+}
@@ -0,0 +1,72 @@
+use messiah_mpk::MPKFileReader;
+
+mod common;
+use common::write_tree;
+
+#[test]
+fn entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let reader = MPKFileReader::new(write_tree(dir.path())).unwrap();
+    let entries: Vec<_> = reader
+        .entries()
+        .map(|entry| {
+            (
+                entry.index(),
+                entry.name(),
+                entry.is_folder(),
+                entry.shard(),
+                entry.size(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            (0, "Script".to_string(), true, 0, 0),
+            (1, "Script/a.py".to_string(), false, 0, 10),
+            (2, "res/ui/button.png".to_string(), false, 0, 3),
+            (3, "Empty".to_string(), true, 0, 0),
+            (4, "readme.txt".to_string(), false, 0, 7),
+            (5, "res\\ui\\icon.png".to_string(), false, 0, 4),
+            (6, "Script/b.py".to_string(), false, 0, entries[6].4),
+            (7, "res/broken.bin".to_string(), false, 0, 16),
+        ]
+    );
+    let entry = reader.entries().nth(4).unwrap();
+    assert_eq!(
+        (entry.version(), entry.hash(), entry.offset()),
+        (1, None, 13)
+    );
+    assert_eq!(entry.open().unwrap().into_inner(), b"read me");
+    assert!(reader.entries().next().unwrap().open().is_err());
+}
+
+#[test]
+fn folder_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = MPKFileReader::new(write_tree(dir.path()))
+        .unwrap()
+        .folder_tree();
+
+    let folders: Vec<_> = root
+        .walk()
+        .map(|folder| (folder.path.as_str(), folder.index, folder.files.clone()))
+        .collect();
+    assert_eq!(
+        folders,
+        [
+            ("", None, vec![4]),
+            ("Script", Some(0), vec![1, 6]),
+            ("res", None, vec![7]),
+            ("res/ui", None, vec![2, 5]),
+            ("Empty", Some(3), vec![]),
+        ]
+    );
+
+    let ui = root.find("res/ui").unwrap();
+    assert_eq!(ui.name(), "ui");
+    assert_eq!(root.find("res").unwrap().find("ui").unwrap().path, "res/ui");
+    assert_eq!(root.find("res\\ui").unwrap().path, "res/ui");
+    assert!(root.find("res/missing").is_none());
+    assert_eq!(root.find("").unwrap().name(), "");
+}
@@ -0,0 +1,163 @@
+use messiah_mpk::{
+    ExtractOptions, MPKCompression, MPKError, MPKFileReader, PyObject, PythonVersion,
+};
+
+mod common;
+use common::{mpk_error, read_tree, write_archive, write_index};
+
+const SAMPLE_311: &[u8] = include_bytes!("data/sample_311.pyc");
+
+/// A `CCCC` container with a codec nobody knows
+const UNKNOWN: &[u8] = b"CCCCZSTD\x05\x00\x00\x00data";
+
+#[test]
+fn truncated_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_archive(
+        dir.path(),
+        &[
+            ("a.txt", MPKCompression::None, b"first"),
+            ("b.txt", MPKCompression::None, b"second"),
+            ("c.txt", MPKCompression::None, b"third"),
+        ],
+    );
+    let index = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &index[..index.len() - 3]).unwrap();
+
+    let err = MPKFileReader::new(&path).unwrap_err();
+    assert!(matches!(
+        mpk_error(&err),
+        MPKError::TruncatedIndex { read: 2, count: 3 }
+    ));
+}
+
+#[test]
+fn missing_shard() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_archive(dir.path(), &[("a.txt", MPKCompression::None, b"first")]);
+    write_index(&path, &[("a.txt", 0, 0, 5), ("b.txt", 2, 0, 5)]);
+
+    let reader = MPKFileReader::new(&path).unwrap();
+    assert_eq!(reader.open_entry(0).unwrap().into_inner(), b"first");
+    let err = reader.open_entry(1).unwrap_err();
+    match mpk_error(&err) {
+        MPKError::MissingShard(shard) => assert_eq!(shard, &dir.path().join("test2.mpk")),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn entry_out_of_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_archive(dir.path(), &[("a.txt", MPKCompression::None, b"first")]);
+    write_index(&path, &[("a.txt", 0, 0, 5), ("b.txt", 0, 3, 5)]);
+
+    let err = MPKFileReader::new(&path)
+        .unwrap()
+        .open_entry(1)
+        .unwrap_err();
+    match mpk_error(&err) {
+        MPKError::EntryOutOfRange(name) => assert_eq!(name, "b.txt"),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn unknown_container() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_archive(dir.path(), &[("a.bin", MPKCompression::None, UNKNOWN)]);
+
+    let err = MPKFileReader::new(&path)
+        .unwrap()
+        .open_entry(0)
+        .unwrap_err();
+    match mpk_error(&err) {
+        MPKError::UnknownContainer { name, magic } => {
+            assert_eq!((name.as_str(), magic.as_str()), ("a.bin", "CCCCZSTD"))
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn invalid_marshal() {
+    let dir = tempfile::tempdir().unwrap();
+    let truncated = &SAMPLE_311[..SAMPLE_311.len() / 2];
+    let path = write_archive(
+        dir.path(),
+        &[("a.bin", MPKCompression::MangledZlib, truncated)],
+    );
+    let reader = MPKFileReader::new(&path).unwrap();
+
+    // The entry still extracts, named after the detected file type instead of the script
+    let entry = reader.open_entry(0).unwrap();
+    assert_eq!(entry.name(), "a.pyc");
+    let err =
+        PyObject::from_marshal(&entry.into_inner()[16..], PythonVersion::Version3_11).unwrap_err();
+    assert!(matches!(err, MPKError::InvalidMarshal(_)), "{:?}", err);
+}
+
+#[test]
+fn keep_going_collects_failures() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_archive(
+        dir.path(),
+        &[
+            ("a.txt", MPKCompression::None, b"first"),
+            ("b.bin", MPKCompression::None, UNKNOWN),
+            ("c.txt", MPKCompression::None, b"third"),
+        ],
+    );
+    write_index(
+        &path,
+        &[
+            ("a.txt", 0, 0, 5),
+            ("b.bin", 0, 5, 16),
+            ("c.txt", 0, 21, 5),
+            ("gone.txt", 4, 0, 5),
+            ("past.txt", 0, 20, 10),
+        ],
+    );
+    let reader = MPKFileReader::new(&path).unwrap();
+
+    let err = reader
+        .extract_files_with(dir.path().join("stopped"), &ExtractOptions::default())
+        .unwrap_err();
+    assert!(matches!(mpk_error(&err), MPKError::UnknownContainer { .. }));
+
+    let out = dir.path().join("out");
+    let options = ExtractOptions {
+        keep_going: true,
+        ..Default::default()
+    };
+    let summary = reader.extract_files_with(&out, &options).unwrap();
+    assert_eq!(summary.extracted, 2);
+    let failures: Vec<_> = summary
+        .failures
+        .iter()
+        .map(|failure| {
+            let kind = match mpk_error(&failure.error) {
+                MPKError::UnknownContainer { .. } => "unknown container",
+                MPKError::MissingShard(_) => "missing shard",
+                MPKError::EntryOutOfRange(_) => "out of range",
+                other => panic!("{:?}", other),
+            };
+            (failure.index, failure.name.as_str(), kind)
+        })
+        .collect();
+    assert_eq!(
+        failures,
+        [
+            (1, "b.bin", "unknown container"),
+            (3, "gone.txt", "missing shard"),
+            (4, "past.txt", "out of range"),
+        ]
+    );
+    assert_eq!(
+        read_tree(&out),
+        [
+            ("a.txt".to_string(), b"first".to_vec()),
+            ("c.txt".to_string(), b"third".to_vec()),
+        ]
+    );
+}
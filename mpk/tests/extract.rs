@@ -0,0 +1,204 @@
+use messiah_mpk::{ExtractFormat, ExtractManifest, ExtractOptions, MPKCompression, MPKFileReader};
+
+mod common;
+use common::{build, payload, read_tree, write_archive};
+
+#[test]
+fn paths_leaving_the_output_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let reader = build(
+        dir.path(),
+        &[
+            ("../escaped.txt", b"parent"),
+            ("/tmp/absolute.txt", b"root"),
+            ("inside/../kept.txt", b"kept"),
+            ("fine.txt", b"fine"),
+        ],
+    );
+    for format in [ExtractFormat::Directory, ExtractFormat::Zip] {
+        let out = dir.path().join(format!("out/{:?}", format));
+        let options = ExtractOptions {
+            format,
+            keep_going: true,
+            ..Default::default()
+        };
+        let summary = reader.extract_files_with(&out, &options).unwrap();
+        let failed: Vec<_> = summary
+            .failures
+            .iter()
+            .map(|failure| failure.index)
+            .collect();
+        assert_eq!(failed, [0, 1, 2]);
+        assert_eq!(summary.extracted, 1);
+    }
+    assert!(!dir.path().join("out/escaped.txt").exists());
+    assert_eq!(
+        std::fs::read(dir.path().join("out/Directory/fine.txt")).unwrap(),
+        b"fine"
+    );
+}
+
+#[test]
+fn mangled_zlib_is_written_decoded() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = payload(20);
+    let entries = [
+        ("a.bin", MPKCompression::MangledZlib, data.as_slice()),
+        (
+            "b.txt",
+            MPKCompression::MangledZlib,
+            b"not a script".as_slice(),
+        ),
+    ];
+    let reader = MPKFileReader::new(write_archive(dir.path(), &entries)).unwrap();
+    let out = dir.path().join("out");
+    reader.extract_files(&out).unwrap();
+
+    // Entries that aren't scripts used to be written with their payload still mangled
+    assert_eq!(
+        read_tree(&out),
+        [
+            ("a.bin".to_string(), data),
+            ("b.txt".to_string(), b"not a script".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn parallel_matches_sequential() {
+    let dir = tempfile::tempdir().unwrap();
+    let names: Vec<_> = (0..60)
+        .map(|index| format!("data/{}/{}.bin", index % 50 % 4, index % 50))
+        .collect();
+    let payloads: Vec<_> = (0..60).map(payload).collect();
+    let entries: Vec<_> = names
+        .iter()
+        .zip(&payloads)
+        .map(|(name, data)| (name.as_str(), data.as_slice()))
+        .collect();
+    let reader = build(dir.path(), &entries);
+
+    let mut trees = Vec::new();
+    // Small batches make duplicates land in different batches
+    for (threads, max_memory) in [(1, None), (8, None), (8, Some(4096))] {
+        let out = dir.path().join(format!("out{}_{:?}", threads, max_memory));
+        let options = ExtractOptions {
+            threads: Some(threads),
+            max_memory,
+            ..Default::default()
+        };
+        let summary = reader.extract_files_with(&out, &options).unwrap();
+        assert_eq!(summary.extracted, 60);
+        trees.push(read_tree(&out));
+    }
+    assert_eq!(trees[0].len(), 50);
+    assert_eq!(trees[0], trees[1]);
+    assert_eq!(trees[0], trees[2]);
+    // Later entries replace earlier ones with the same name
+    let last = trees[0]
+        .iter()
+        .find(|(name, _)| name == "data/1/5.bin")
+        .unwrap();
+    assert_eq!(last.1, payloads[55]);
+}
+
+#[test]
+fn manifest_records_every_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let reader = build(
+        dir.path(),
+        &[
+            ("a.txt", b"first"),
+            ("b/c.txt", b"second"),
+            ("d.txt", b"third"),
+        ],
+    );
+    let path = dir.path().join("manifest.json");
+    let options = ExtractOptions {
+        manifest: Some(path.clone()),
+        ..Default::default()
+    };
+    reader
+        .extract_files_with(dir.path().join("out"), &options)
+        .unwrap();
+
+    let manifest = ExtractManifest::read(&path).unwrap();
+    let entries: Vec<_> = manifest
+        .entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.index,
+                entry.path.as_str(),
+                entry.container.as_deref(),
+                entry.decoded_size,
+            )
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            (0, "a.txt", Some("raw"), Some(5)),
+            (1, "b/c.txt", Some("ZZZ4"), Some(6)),
+            (2, "d.txt", Some("E2 06 zlib"), Some(5)),
+        ]
+    );
+    assert!(manifest.entries.iter().all(|entry| entry.digest.is_some()));
+}
+
+#[test]
+fn incremental_skips_unchanged_and_prunes() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+    std::fs::create_dir_all(&old).unwrap();
+    std::fs::create_dir_all(&new).unwrap();
+    let reader = build(
+        &old,
+        &[
+            ("a.txt", b"first"),
+            ("b/c.txt", b"second"),
+            ("d.txt", b"third"),
+        ],
+    );
+    let options = ExtractOptions {
+        incremental: true,
+        ..Default::default()
+    };
+
+    let summary = reader.extract_files_with(&out, &options).unwrap();
+    assert_eq!((summary.extracted, summary.unchanged), (3, 0));
+
+    // Nothing changed, so nothing is written until an output is edited
+    let summary = reader.extract_files_with(&out, &options).unwrap();
+    assert_eq!((summary.extracted, summary.unchanged), (0, 3));
+    std::fs::write(out.join("a.txt"), b"edited").unwrap();
+    let summary = reader.extract_files_with(&out, &options).unwrap();
+    assert_eq!((summary.extracted, summary.unchanged), (1, 2));
+    assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"first");
+
+    // An entry changed and one is gone from the archive
+    let reader = build(&new, &[("a.txt", b"first"), ("b/c.txt", b"changed")]);
+    let summary = reader
+        .extract_files_with(
+            &out,
+            &ExtractOptions {
+                prune: true,
+                ..options
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        (summary.extracted, summary.unchanged, summary.removed),
+        (1, 1, 1)
+    );
+    assert_eq!(std::fs::read(out.join("b/c.txt")).unwrap(), b"changed");
+    assert!(!out.join("d.txt").exists());
+    let manifest = ExtractManifest::read(ExtractManifest::default_path(&out)).unwrap();
+    let paths: Vec<_> = manifest
+        .entries
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect();
+    assert_eq!(paths, ["a.txt", "b/c.txt"]);
+}
@@ -0,0 +1,40 @@
+use messiah_mpk::{ExtractFilter, GrepQuery, MPKCompression, MPKFileReader};
+
+mod common;
+use common::write_archive;
+
+#[test]
+fn matches_inside_compressed_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let text = "padding ".repeat(64) + "needle";
+    let utf16: Vec<u8> = "needle".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let path = write_archive(
+        dir.path(),
+        &[
+            ("raw.txt", MPKCompression::None, b"no match here"),
+            ("lz4.txt", MPKCompression::Lz4, text.as_bytes()),
+            ("zlib.txt", MPKCompression::MangledZlib, text.as_bytes()),
+            ("wide.bin", MPKCompression::Lz4, &utf16),
+        ],
+    );
+
+    let reader = MPKFileReader::new(&path).unwrap();
+    let mut query = GrepQuery::default();
+    query.add_string("needle").unwrap();
+    let report = reader.grep(&query, &ExtractFilter::default()).unwrap();
+    assert_eq!(report.searched, 4);
+    assert!(report.failures.is_empty());
+    let hits: Vec<_> = report
+        .hits
+        .iter()
+        .map(|hit| (hit.index, hit.pattern.as_str(), hit.offset, hit.size))
+        .collect();
+    assert_eq!(
+        hits,
+        [
+            (1, "\"needle\" (UTF-8)", 512, 6),
+            (2, "\"needle\" (UTF-8)", 512, 6),
+            (3, "\"needle\" (UTF-16LE)", 0, 12),
+        ]
+    );
+}
@@ -0,0 +1,128 @@
+use messiah_mpk::{MPKCompression, MPKFileReader};
+use serde_json::json;
+
+mod common;
+use common::{write_archive, write_index, write_package};
+
+/// 100 bytes that are never zero
+fn data(seed: u8) -> Vec<u8> {
+    (0..100).map(|byte| byte as u8 * 2 + seed).collect()
+}
+
+/// A shard of three raw 100 byte entries, written by [`common::write_archive`]
+fn write_shard(dir: &std::path::Path) -> std::path::PathBuf {
+    let payloads = [data(2), data(4), data(8)];
+    let entries: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .zip(&payloads)
+        .map(|(name, data)| (*name, MPKCompression::None, data.as_slice()))
+        .collect();
+    write_archive(dir, &entries)
+}
+
+#[test]
+fn written_archive_is_ok() {
+    let dir = tempfile::tempdir().unwrap();
+    let reader = MPKFileReader::new(write_shard(dir.path())).unwrap();
+    let report = reader.check_integrity().unwrap();
+    assert!(report.is_ok());
+    assert!(report.gaps.is_empty());
+    assert_eq!(
+        serde_json::to_value(&report.shards).unwrap(),
+        json!([{"shard": 0, "size": 300, "entries": 3, "referenced": 300}])
+    );
+}
+
+#[test]
+fn overlaps_gaps_and_entries_past_the_end() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_shard(dir.path());
+    write_index(
+        &path,
+        &[
+            ("a", 0, 0, 100),
+            ("b", 0, 50, 100),
+            ("same_as_a", 0, 0, 100),
+            ("past_end", 0, 200, 200),
+            ("empty", 0, 120, 0),
+        ],
+    );
+
+    let reader = MPKFileReader::new(&path).unwrap();
+    let report = reader.check_integrity().unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(
+        serde_json::to_value(&report).unwrap(),
+        json!({
+            "shards": [{"shard": 0, "size": 300, "entries": 5, "referenced": 250}],
+            "issues": [
+                {
+                    "kind": "overlap", "shard": 0, "first": 0, "second": 2,
+                    "offset": 0, "size": 100, "identical": true
+                },
+                {
+                    "kind": "overlap", "shard": 0, "first": 0, "second": 1,
+                    "offset": 50, "size": 50, "identical": false
+                },
+                {
+                    "kind": "overlap", "shard": 0, "first": 1, "second": 2,
+                    "offset": 50, "size": 50, "identical": false
+                },
+                {"kind": "past_end", "index": 3, "shard": 0, "end": 400, "shard_size": 300}
+            ],
+            "gaps": [{"shard": 0, "offset": 150, "size": 50, "zeroed": false}]
+        })
+    );
+
+    // The gap holds what used to be the second half of the second entry
+    let mut gap = Vec::new();
+    reader.write_gap(&report.gaps[0], &mut gap).unwrap();
+    assert_eq!(gap, data(4)[50..]);
+}
+
+#[test]
+fn missing_shard() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_shard(dir.path());
+    write_index(
+        &path,
+        &[("a", 0, 0, 100), ("b", 3, 0, 10), ("c", 3, 10, 10)],
+    );
+
+    let report = MPKFileReader::new(&path)
+        .unwrap()
+        .check_integrity()
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&report.issues).unwrap(),
+        json!([{"kind": "missing_shard", "shard": 3, "entries": 2}])
+    );
+    assert_eq!(report.shards[1].size, None);
+    // Everything behind the first entry is unreferenced
+    assert_eq!(report.gaps.len(), 1);
+}
+
+#[test]
+fn compressed_shard() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_shard(dir.path());
+    let package = dir.path().join("game.apk");
+    write_package(
+        &package,
+        &[
+            ("assets/test.mpkinfo", &path, false),
+            ("assets/test.mpk", &dir.path().join("test.mpk"), true),
+        ],
+    );
+
+    let reader = MPKFileReader::new(package.join("assets/test.mpkinfo")).unwrap();
+    let report = reader.check_integrity().unwrap();
+    assert_eq!(
+        serde_json::to_value(&report).unwrap(),
+        json!({
+            "shards": [{"shard": 0, "size": null, "entries": 3, "referenced": 0}],
+            "issues": [{"kind": "compressed_shard", "shard": 0, "entries": 3}],
+            "gaps": []
+        })
+    );
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+use std::process::Command;
+
+use messiah_mpk::MPKFileReader;
+use serde_json::json;
+
+mod common;
+use common::write_tree;
+
+#[test]
+fn list_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let reader = MPKFileReader::new(write_tree(dir.path())).unwrap();
+    let listed: Vec<_> = reader
+        .list_entries()
+        .unwrap()
+        .into_iter()
+        .map(|info| {
+            (
+                info.name,
+                info.container,
+                info.decompressed_size,
+                info.error.is_some(),
+            )
+        })
+        .collect();
+    let raw = Some("raw".to_string());
+
+    assert_eq!(
+        listed,
+        [
+            ("Script".to_string(), None, None, false),
+            ("Script/a.py".to_string(), raw.clone(), Some(10), false),
+            ("res/ui/button.png".to_string(), raw.clone(), Some(3), false),
+            ("Empty".to_string(), None, None, false),
+            ("readme.txt".to_string(), raw.clone(), Some(7), false),
+            ("res\\ui\\icon.png".to_string(), raw, Some(4), false),
+            (
+                "Script/b.txt".to_string(),
+                Some("E2 06 zlib".to_string()),
+                Some(10),
+                false
+            ),
+            (
+                "res/broken.bin".to_string(),
+                Some("CCCC".to_string()),
+                None,
+                true
+            ),
+        ]
+    );
+}
+
+/// Runs `messiah-mpk list` on the archive at `path`, returning what it printed
+fn list(path: &Path, format: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_messiah-mpk"))
+        .args(["list", "--format", format])
+        .arg(path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn list_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_tree(dir.path());
+
+    let json: serde_json::Value = serde_json::from_str(&list(&path, "json")).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 8);
+    assert_eq!(
+        json[4],
+        json!({
+            "index": 4, "shard": 0, "offset": 13, "size": 7, "is_folder": false,
+            "hash": null, "container": "raw", "decompressed_size": 7,
+            "name": "readme.txt", "error": null
+        })
+    );
+    assert!(json[7]["error"].is_string());
+
+    let csv = list(&path, "csv");
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("index,shard,offset,size,is_folder,hash,container,decompressed_size,name,error")
+    );
+    assert_eq!(lines.nth(4), Some("4,0,13,7,false,,raw,7,readme.txt,"));
+    assert_eq!(lines.count(), 3);
+
+    let table = list(&path, "table");
+    let rows: Vec<Vec<_>> = table
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows.len(), 9);
+    assert_eq!(rows[0].last(), Some(&"name"));
+    assert_eq!(
+        rows[5],
+        ["4", "0", "13", "7", "false", "raw", "7", "readme.txt"]
+    );
+    assert!(table.lines().last().unwrap().contains("(error: "));
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use messiah_mpk::{PyCode, PyObject, PythonVersion};
+
+/// `tests/data/sample.py` compiled by each CPython release as `Script/sample.py`
+const SAMPLES: [(&[u8], PythonVersion); 7] = [
+    (
+        include_bytes!("data/sample_27.pyc"),
+        PythonVersion::Version2_7,
+    ),
+    (
+        include_bytes!("data/sample_36.pyc"),
+        PythonVersion::Version3_6,
+    ),
+    (
+        include_bytes!("data/sample_38.pyc"),
+        PythonVersion::Version3_8,
+    ),
+    (
+        include_bytes!("data/sample_310.pyc"),
+        PythonVersion::Version3_10,
+    ),
+    (
+        include_bytes!("data/sample_311.pyc"),
+        PythonVersion::Version3_11,
+    ),
+    (
+        include_bytes!("data/sample_312.pyc"),
+        PythonVersion::Version3_12,
+    ),
+    (
+        include_bytes!("data/sample_313.pyc"),
+        PythonVersion::Version3_13,
+    ),
+];
+
+fn code_consts(code: &PyCode) -> Vec<&PyCode> {
+    match code.consts.resolve() {
+        PyObject::Tuple(consts) => consts.iter().filter_map(PyObject::as_code).collect(),
+        other => panic!("consts are not a tuple: {:?}", other),
+    }
+}
+
+fn check_sample(module: &PyObject) {
+    let module = module.as_code().unwrap();
+    assert_eq!(module.filename.as_str(), Some("Script/sample.py"));
+    assert_eq!(module.name.as_str(), Some("<module>"));
+
+    let consts = code_consts(module);
+    let names: Vec<_> = consts
+        .iter()
+        .map(|code| code.name.as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["greet", "Greeter"]);
+    assert_eq!(consts[0].argcount, 2);
+    assert_eq!(consts[0].firstlineno, 6);
+    let methods: Vec<_> = code_consts(consts[1])
+        .iter()
+        .map(|code| code.name.as_str().unwrap())
+        .collect();
+    assert_eq!(methods, ["__init__", "greet"]);
+}
+
+/// Fields of the code object that only exist in some releases
+fn check_layout(code: &PyCode, version: PythonVersion) {
+    use PythonVersion::*;
+
+    assert_eq!(code.kwonlyargcount.is_some(), version >= Version3_0);
+    assert_eq!(code.posonlyargcount.is_some(), version >= Version3_8);
+    let localsplus = version >= Version3_11;
+    assert_eq!(code.nlocals.is_none(), localsplus);
+    assert_eq!(code.varnames.is_none(), localsplus);
+    assert_eq!(code.freevars.is_none(), localsplus);
+    assert_eq!(code.cellvars.is_none(), localsplus);
+    assert_eq!(code.localsplusnames.is_some(), localsplus);
+    assert_eq!(code.localspluskinds.is_some(), localsplus);
+    assert_eq!(code.qualname.is_some(), localsplus);
+    assert_eq!(code.exceptiontable.is_some(), localsplus);
+}
+
+#[test]
+fn compiled_samples() {
+    for (pyc, version) in SAMPLES {
+        let data = &pyc[version.pyc_header_size()..];
+        let module = PyObject::from_marshal(data, version)
+            .unwrap_or_else(|err| panic!("{:?}: {}", version, err));
+        check_sample(&module);
+        let module = module.as_code().unwrap();
+        check_layout(module, version);
+        for code in code_consts(module) {
+            check_layout(code, version);
+        }
+    }
+}
+
+#[test]
+fn shared_file_names() {
+    let (pyc, version) = SAMPLES[4];
+    let module = PyObject::from_marshal(&pyc[16..], version).unwrap();
+    let module = module.as_code().unwrap();
+    assert_eq!(module.posonlyargcount, Some(0));
+    assert_eq!(
+        module.qualname.as_ref().and_then(PyObject::as_str),
+        Some("<module>")
+    );
+
+    // The file name is stored once and referenced by every other code object
+    let greet = code_consts(module)[0];
+    match (&module.filename, &greet.filename) {
+        (PyObject::Ref(module), PyObject::Ref(greet)) => assert!(Arc::ptr_eq(module, greet)),
+        other => panic!("file names are not shared: {:?}", other),
+    }
+}
+
+#[test]
+fn python_22_code() {
+    // Before 2.3 the counts and the first line number are shorts
+    let mut data = b"c".to_vec();
+    for count in [1u16, 1, 2, 0x43] {
+        data.extend(count.to_le_bytes());
+    }
+    data.extend(b"s\x04\x00\x00\x00|\x00\x00S");
+    data.extend(b"(\x01\x00\x00\x00N");
+    data.extend(b"(\x00\x00\x00\x00");
+    data.extend(b"(\x01\x00\x00\x00s\x01\x00\x00\x00x");
+    data.extend(b"(\x00\x00\x00\x00".repeat(2));
+    data.extend(b"s\x0b\x00\x00\x00Script/a.pys\x01\x00\x00\x00f");
+    data.extend(7u16.to_le_bytes());
+    data.extend(b"s\x00\x00\x00\x00");
+
+    let code = PyObject::from_marshal(&data, PythonVersion::Version2_2).unwrap();
+    let code = code.as_code().unwrap();
+    assert_eq!(
+        (code.argcount, code.nlocals, code.stacksize),
+        (1, Some(1), 2)
+    );
+    assert_eq!(code.flags, 0x43);
+    assert_eq!(code.filename.as_str(), Some("Script/a.py"));
+    assert_eq!(code.firstlineno, 7);
+    check_layout(code, PythonVersion::Version2_2);
+
+    // The same bytes don't line up with the wider counts of later releases
+    assert!(PyObject::from_marshal(&data, PythonVersion::Version2_7).is_err());
+}
+
+#[test]
+fn type_ref() {
+    // A list holding a flagged short ASCII string and a reference to it
+    let data = b"[\x02\x00\x00\x00\xda\x04textr\x00\x00\x00\x00";
+    let PyObject::List(items) = PyObject::from_marshal(data, PythonVersion::Version3_11).unwrap()
+    else {
+        panic!("not a list");
+    };
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_str(), Some("text"));
+    match (&items[0], &items[1]) {
+        (PyObject::Ref(first), PyObject::Ref(second)) => assert!(Arc::ptr_eq(first, second)),
+        other => panic!("not shared: {:?}", other),
+    }
+
+    // References to objects that don't exist yet or are still being read
+    let unknown = b"[\x01\x00\x00\x00r\x00\x00\x00\x00";
+    assert!(PyObject::from_marshal(unknown, PythonVersion::Version3_11).is_err());
+    let unfinished = b"\xa9\x01r\x00\x00\x00\x00";
+    assert!(PyObject::from_marshal(unfinished, PythonVersion::Version3_11).is_err());
+}
+
+#[test]
+fn reference_bomb() {
+    // Every tuple refers to the one before it twice, copying them would take 2^64 objects
+    let levels = 64u32;
+    let mut data = b"[".to_vec();
+    data.extend(levels.to_le_bytes());
+    data.extend(b"\xa9\x02NN");
+    for level in 1..levels {
+        data.extend(b"\xa9\x02");
+        for _ in 0..2 {
+            data.push(b'r');
+            data.extend((level - 1).to_le_bytes());
+        }
+    }
+    let PyObject::List(items) = PyObject::from_marshal(&data, PythonVersion::Version3_11).unwrap()
+    else {
+        panic!("not a list");
+    };
+    assert_eq!(items.len(), levels as usize);
+}
+
+#[test]
+fn deep_nesting() {
+    // Lists of one list each, far deeper than the stack could take
+    let data = b"[\x01\x00\x00\x00".repeat(100_000);
+    assert!(PyObject::from_marshal(&data, PythonVersion::Version3_11).is_err());
+}
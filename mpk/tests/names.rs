@@ -0,0 +1,138 @@
+use messiah_mpk::{
+    MPKCompression, MPKFileReader, MPKFileWriter, NameDictionary, NameHash, NameNormalization,
+};
+
+mod common;
+use common::write_archive;
+
+#[test]
+fn hashes() {
+    assert_eq!(NameHash::Crc32.hash("hello"), 0x3610a686);
+    assert_eq!(NameHash::Fnv1a32.hash("a"), 0xe40c292c);
+    assert_eq!(NameHash::Fnv1_32.hash("a"), 0x050c5d7e);
+    assert_eq!(NameHash::Djb2.hash("a"), 5381 * 33 + 97);
+    assert_eq!(NameHash::Java.hash("ab"), 97 * 31 + 98);
+    assert_eq!(NameHash::Murmur3.hash(""), 0);
+    assert_eq!(NameHash::Murmur3.hash("hello"), 0x248bfa47);
+}
+
+#[test]
+fn normalizations() {
+    let name = "Res/UI/Button.png";
+    assert_eq!(NameNormalization::AsIs.apply(name), name);
+    assert_eq!(
+        NameNormalization::Lowercase.apply(name),
+        "res/ui/button.png"
+    );
+    assert_eq!(
+        NameNormalization::Backslashes.apply(name),
+        "Res\\UI\\Button.png"
+    );
+    assert_eq!(
+        NameNormalization::LowercaseBackslashes.apply(name),
+        "res\\ui\\button.png"
+    );
+}
+
+/// Version 2 archive of `names` hashed with Fnv1a32 of the lowercase path
+fn write_v2(dir: &std::path::Path, names: &[&str]) -> MPKFileReader {
+    let path = dir.join("test.mpkinfo");
+    let mut writer = MPKFileWriter::new(&path, 2).unwrap();
+    for name in names {
+        let hash = NameHash::Fnv1a32.hash(&NameNormalization::Lowercase.apply(name));
+        let mut extension = [0; 3];
+        let name_extension = name.rsplit('.').next().unwrap().as_bytes();
+        let len = name_extension.len().min(3);
+        extension[..len].copy_from_slice(&name_extension[..len]);
+        writer
+            .add_file_v2(extension, hash, name.as_bytes())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    MPKFileReader::new(&path).unwrap()
+}
+
+#[test]
+fn resolves_version_2_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let names = [
+        "Script/Main.py",
+        "Res/UI/Button.png",
+        "Res/Sound/Click.wav",
+        "Res/Unknown.dat",
+    ];
+    let mut reader = write_v2(dir.path(), &names);
+
+    let mut dictionary = NameDictionary::default();
+    for name in &names[..3] {
+        dictionary.add_name(name);
+    }
+    // Backslashes are stored as slashes, the extension has to match the entry as well
+    dictionary.add_name("Res\\Other.png");
+    dictionary.add_name("Script/Main.txt");
+    let resolution = reader.resolve_names(&dictionary).unwrap();
+    assert_eq!(resolution.hash, NameHash::Fnv1a32);
+    assert_eq!(resolution.normalization, NameNormalization::Lowercase);
+    assert_eq!(resolution.names.len(), 3);
+
+    let resolved: Vec<_> = reader.entry_names().collect();
+    let hash = NameHash::Fnv1a32.hash("res/unknown.dat");
+    assert_eq!(
+        resolved,
+        [
+            "Script/Main.py".to_string(),
+            "Res/UI/Button.png".to_string(),
+            "Res/Sound/Click.wav".to_string(),
+            format!("file_0_{}.dat", hash),
+        ]
+    );
+}
+
+#[test]
+fn single_matches_are_not_trusted() {
+    let dir = tempfile::tempdir().unwrap();
+    let names: Vec<_> = (0..10).map(|index| format!("data/{}.bin", index)).collect();
+    let names: Vec<_> = names.iter().map(String::as_str).collect();
+    let reader = write_v2(dir.path(), &names);
+
+    let mut dictionary = NameDictionary::default();
+    dictionary.add_name("data/0.bin");
+    assert!(dictionary.resolve(&reader).is_none());
+    dictionary.add_name("data/1.bin");
+    assert!(dictionary.resolve(&reader).is_none());
+    dictionary.add_name("data/2.bin");
+    assert_eq!(dictionary.resolve(&reader).unwrap().names.len(), 3);
+
+    // Most entries of a small archive are enough
+    let dir = tempfile::tempdir().unwrap();
+    let reader = write_v2(dir.path(), &["data/0.bin", "data/1.bin"]);
+    let mut dictionary = NameDictionary::default();
+    dictionary.add_name("data/0.bin");
+    dictionary.add_name("data/1.bin");
+    assert_eq!(dictionary.resolve(&reader).unwrap().names.len(), 2);
+}
+
+#[test]
+fn python_names_skip_unreadable_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = include_bytes!("data/sample_311.pyc");
+    let path = write_archive(
+        dir.path(),
+        &[
+            ("Script/a.pyc", MPKCompression::MangledZlib, script),
+            ("big.bin", MPKCompression::None, &[0; 4000]),
+        ],
+    );
+    std::fs::remove_file(dir.path().join("test1.mpk")).unwrap();
+
+    let reader = MPKFileReader::new(path).unwrap();
+    let mut dictionary = NameDictionary::default();
+    let failures = dictionary.add_python_names(&reader);
+    assert_eq!(failures.len(), 1);
+    assert_eq!(
+        (failures[0].index, failures[0].name.as_str()),
+        (1, "big.bin")
+    );
+    // The file name with and without the script folder, as source and compiled
+    assert_eq!(dictionary.len(), 4);
+}
@@ -0,0 +1,205 @@
+use messiah_mpk::{MPKError, NPKCompression, NPKFileReader};
+
+/// Compression and encryption halves of an entry's flags
+type Flags = (u16, u16);
+
+const PLAIN: Flags = (0, 0);
+
+/// Writes an `NXPK` archive with the index behind the payloads, extended entries
+/// if `extended` is set and a name table if `names` is
+fn write_npk(
+    path: &std::path::Path,
+    entries: &[(Flags, &[u8], u32)],
+    extended: bool,
+    names: Option<&[&str]>,
+) {
+    let mut data = b"NXPK".to_vec();
+    data.extend((entries.len() as u32).to_le_bytes());
+    let (var1, var3) = if extended { (1u32, 1u32) } else { (0, 0) };
+    for word in [var1, 0, var3, 0] {
+        data.extend(word.to_le_bytes());
+    }
+
+    let mut offsets = Vec::new();
+    for (_, payload, _) in entries {
+        offsets.push(data.len() as u32);
+        data.extend_from_slice(payload);
+    }
+    let index_offset = data.len() as u32;
+    data[20..24].copy_from_slice(&index_offset.to_le_bytes());
+
+    for (index, ((compression, encryption), payload, original_size)) in entries.iter().enumerate() {
+        let flags = (*encryption as u32) << 16 | *compression as u32;
+        let words = [
+            0x1000 + index as u32,
+            offsets[index],
+            payload.len() as u32,
+            *original_size,
+            0,
+            0,
+            flags,
+        ];
+        for word in words {
+            data.extend(word.to_le_bytes());
+        }
+        if extended {
+            data.extend([0xEE; 12]);
+        }
+    }
+
+    if let Some(names) = names {
+        data.extend([0; 16]);
+        for name in names {
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+        }
+    }
+    std::fs::write(path, data).unwrap();
+}
+
+/// Zlib stream with a single stored block
+fn stored_zlib(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01, 0x01];
+    stream.extend((data.len() as u16).to_le_bytes());
+    stream.extend((!(data.len() as u16)).to_le_bytes());
+    stream.extend_from_slice(data);
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend((b << 16 | a).to_be_bytes());
+    stream
+}
+
+fn read_entry(reader: &NPKFileReader, index: usize) -> (String, Vec<u8>) {
+    let entry = reader.open_entry(index).unwrap();
+    (entry.name().to_string(), entry.into_inner())
+}
+
+#[test]
+fn entry_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let lz4 = lz4_flex::block::compress(b"lz4 lz4 lz4 lz4 lz4");
+    let zlib = stored_zlib(b"zlib payload");
+    let entries: [(Flags, &[u8], u32); 3] = [
+        (PLAIN, b"plain text", 10),
+        ((1, 0), &zlib, 12),
+        ((2, 0), &lz4, 19),
+    ];
+
+    for extended in [false, true] {
+        let path = dir.path().join(format!("{}.npk", extended));
+        write_npk(&path, &entries, extended, None);
+        assert!(NPKFileReader::detect(&path).unwrap());
+
+        let reader = NPKFileReader::new(&path).unwrap();
+        let read: Vec<_> = reader
+            .entries()
+            .iter()
+            .map(|entry| {
+                (
+                    entry.hash,
+                    entry.size,
+                    entry.original_size,
+                    entry.compression,
+                )
+            })
+            .collect();
+        assert_eq!(
+            read,
+            [
+                (0x1000, 10, 10, NPKCompression::None),
+                (0x1001, zlib.len() as u32, 12, NPKCompression::Zlib),
+                (0x1002, lz4.len() as u32, 19, NPKCompression::Lz4),
+            ]
+        );
+        assert_eq!(read_entry(&reader, 0).1, b"plain text");
+        assert_eq!(read_entry(&reader, 1).1, b"zlib payload");
+        assert_eq!(read_entry(&reader, 2).1, b"lz4 lz4 lz4 lz4 lz4");
+    }
+}
+
+#[test]
+fn name_table() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("names.npk");
+    let entries: [(Flags, &[u8], u32); 2] = [(PLAIN, b"first", 5), (PLAIN, b"second", 6)];
+    for extended in [false, true] {
+        write_npk(
+            &path,
+            &entries,
+            extended,
+            Some(&["res\\a.txt", "res\\b\\c.bin"]),
+        );
+        let reader = NPKFileReader::new(&path).unwrap();
+        assert_eq!(reader.entry_name(0), "res/a.txt");
+        assert_eq!(
+            read_entry(&reader, 1),
+            ("res/b/c.bin".into(), b"second".to_vec())
+        );
+    }
+
+    // Names are only used if every entry has one
+    write_npk(&path, &entries, false, Some(&["only.txt"]));
+    let reader = NPKFileReader::new(&path).unwrap();
+    assert!(reader.entries().iter().all(|entry| entry.name.is_none()));
+    assert_eq!(reader.entry_name(1), "file_1_4097");
+}
+
+#[test]
+fn unsupported_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("unsupported.npk");
+    let entries: [(Flags, &[u8], u32); 3] = [
+        ((0, 3), b"secret", 6),
+        ((7, 0), b"packed", 6),
+        (PLAIN, b"fine", 4),
+    ];
+    write_npk(&path, &entries, false, None);
+    let reader = NPKFileReader::new(&path).unwrap();
+    assert_eq!(reader.entries()[0].encryption, 3);
+    assert_eq!(reader.entries()[1].compression, NPKCompression::Unknown(7));
+
+    let err = reader.open_entry(0).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MPKError>(),
+        Some(MPKError::EncryptedEntry(_, 3))
+    ));
+    let err = reader.open_entry(1).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MPKError>(),
+        Some(MPKError::UnsupportedCompression(_, 7))
+    ));
+
+    let infos = reader.list_entries().unwrap();
+    let listed: Vec<_> = infos
+        .iter()
+        .map(|info| (info.container.as_deref(), info.error.is_some()))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            (Some("encrypted (3)"), false),
+            (Some("unknown (7)"), true),
+            (Some("raw"), false),
+        ]
+    );
+}
+
+#[test]
+fn truncated_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("truncated.npk");
+    write_npk(&path, &[(PLAIN, b"data", 4)], true, None);
+    let mut data = std::fs::read(&path).unwrap();
+    // Two entries announced, only one in the index
+    data[4] = 2;
+    std::fs::write(&path, &data).unwrap();
+
+    let err = NPKFileReader::new(&path).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MPKError>(),
+        Some(MPKError::TruncatedIndex { read: 1, count: 2 })
+    ));
+}
@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+
+use messiah_mpk::{MPKCompression, MPKError, MPKFileReader};
+
+mod common;
+use common::{mpk_error, payload, read_tree, write_archive, write_package};
+
+/// Raw entries large enough to spread over the first two shards
+fn write_shards(dir: &Path) -> Vec<Vec<u8>> {
+    let payloads = vec![payload(40), payload(41), payload(3)];
+    let entries: Vec<_> = ["a.bin", "b/c.bin", "d.txt"]
+        .into_iter()
+        .zip(&payloads)
+        .map(|(name, data)| (name, MPKCompression::None, data.as_slice()))
+        .collect();
+    let reader = MPKFileReader::new(write_archive(dir, &entries)).unwrap();
+    let shards: Vec<_> = reader.entries().map(|entry| entry.shard()).collect();
+    assert_eq!(shards, [0, 1, 1]);
+    payloads
+}
+
+/// Packs the archive in `dir` into `game.apk` below `assets/`, deflating the shards
+/// in `deflated` and leaving out those in `missing`
+fn package(dir: &Path, deflated: &[u32], missing: &[u32]) -> PathBuf {
+    let package = dir.join("game.apk");
+    let mut members = vec![("assets/test.mpkinfo", dir.join("test.mpkinfo"), true)];
+    for (shard, name) in ["assets/test.mpk", "assets/test1.mpk"]
+        .into_iter()
+        .enumerate()
+    {
+        let shard = shard as u32;
+        if !missing.contains(&shard) {
+            let file = dir.join(Path::new(name).file_name().unwrap());
+            members.push((name, file, deflated.contains(&shard)));
+        }
+    }
+    let members: Vec<_> = members
+        .iter()
+        .map(|(name, file, deflated)| (*name, file.as_path(), *deflated))
+        .collect();
+    write_package(&package, &members);
+    package
+}
+
+#[test]
+fn stored_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    let payloads = write_shards(dir.path());
+    let package = package(dir.path(), &[], &[]);
+
+    // The path runs through the package, the index itself may be deflated
+    let reader = MPKFileReader::new(package.join("assets/test.mpkinfo")).unwrap();
+    for (index, data) in payloads.iter().enumerate() {
+        assert_eq!(&reader.open_entry(index).unwrap().into_inner(), data);
+    }
+
+    // Extracting from the package writes what extracting the shards on disk does
+    let (packed, direct) = (dir.path().join("packed"), dir.path().join("direct"));
+    let summary = reader.extract_files(&packed).unwrap();
+    assert_eq!(summary.extracted, 3);
+    let reader = MPKFileReader::new(dir.path().join("test.mpkinfo")).unwrap();
+    reader.extract_files(&direct).unwrap();
+    assert_eq!(read_tree(&packed), read_tree(&direct));
+}
+
+#[test]
+fn deflated_shard() {
+    let dir = tempfile::tempdir().unwrap();
+    let payloads = write_shards(dir.path());
+    let package = package(dir.path(), &[1], &[]);
+
+    let reader = MPKFileReader::new(package.join("assets/test.mpkinfo")).unwrap();
+    assert_eq!(reader.open_entry(0).unwrap().into_inner(), payloads[0]);
+    let err = reader.open_entry(1).unwrap_err();
+    match mpk_error(&err) {
+        MPKError::CompressedShard(path) => {
+            assert_eq!(path, &package.join("assets/test1.mpk"))
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn missing_shard() {
+    let dir = tempfile::tempdir().unwrap();
+    write_shards(dir.path());
+    let package = package(dir.path(), &[], &[1]);
+
+    let reader = MPKFileReader::new(package.join("assets/test.mpkinfo")).unwrap();
+    assert!(reader.open_entry(0).is_ok());
+    let err = reader.open_entry(2).unwrap_err();
+    assert!(matches!(mpk_error(&err), MPKError::MissingShard(_)));
+}
+
+#[test]
+fn paths_into_packages() {
+    let dir = tempfile::tempdir().unwrap();
+    write_shards(dir.path());
+    let package = package(dir.path(), &[], &[]);
+
+    // Members that don't exist, paths that don't pass a package and packages that
+    // aren't zips
+    assert!(MPKFileReader::new(package.join("assets/other.mpkinfo")).is_err());
+    assert!(MPKFileReader::new(dir.path().join("nowhere/test.mpkinfo")).is_err());
+    assert!(MPKFileReader::new(dir.path().join("test.mpk/test.mpkinfo")).is_err());
+}
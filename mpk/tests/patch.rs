@@ -0,0 +1,96 @@
+use std::io::Read;
+use std::path::Path;
+
+use messiah_mpk::{
+    MPKCompression, MPKError, MPKFileReader, MPKFileWriter, MPKPatchAction, MPKPatcher,
+};
+
+mod common;
+use common::{mpk_error, read_tree, write_archive};
+
+fn read_entry(path: &Path, name: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    MPKFileReader::new(path)
+        .unwrap()
+        .open_entry_by_name(name)
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    data
+}
+
+#[test]
+fn undo_restores_index_and_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_archive(
+        dir.path(),
+        &[
+            ("a.txt", MPKCompression::None, b"first"),
+            ("b.txt", MPKCompression::None, b"second"),
+        ],
+    );
+    let original = read_tree(dir.path());
+
+    // The second patch spills into a new shard
+    for (name, data) in [("a.txt", vec![b'a'; 64]), ("c.txt", vec![b'c'; 64])] {
+        let mut patcher = MPKPatcher::open(&path).unwrap();
+        patcher.set_max_shard_size(100);
+        let action = patcher.replace_file(name, &data).unwrap();
+        assert_eq!(action == MPKPatchAction::Added, name == "c.txt");
+        patcher.finish().unwrap();
+        assert_eq!(read_entry(&path, name), data);
+    }
+    assert!(dir.path().join("test1.mpk").exists());
+    assert_eq!(read_entry(&path, "b.txt"), b"second");
+
+    MPKPatcher::undo(&path).unwrap();
+    assert_eq!(read_tree(dir.path()), original);
+    assert_eq!(read_entry(&path, "a.txt"), b"first");
+}
+
+#[test]
+fn folders_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.mpkinfo");
+    let mut writer = MPKFileWriter::new(&path, 1).unwrap();
+    writer.add_folder("Script").unwrap();
+    writer.finish().unwrap();
+    assert!(!dir.path().join("test.mpk").exists());
+
+    let mut patcher = MPKPatcher::open(&path).unwrap();
+    let action = patcher.replace_file("Script/a.py", b"print('a')").unwrap();
+    assert_eq!(action, MPKPatchAction::Added);
+    patcher.finish().unwrap();
+    assert_eq!(read_entry(&path, "Script/a.py"), b"print('a')");
+}
+
+#[test]
+fn version_2_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.mpkinfo");
+    let mut writer = MPKFileWriter::new(&path, 2).unwrap();
+    writer.add_file_v2(*b"tex", 1234, b"first").unwrap();
+    writer.finish().unwrap();
+
+    let mut patcher = MPKPatcher::open(&path).unwrap();
+    let action = patcher
+        .replace_file("res/file_0_1234.tex", b"patched")
+        .unwrap();
+    assert_eq!(action, MPKPatchAction::Replaced);
+    let action = patcher.replace_file("file_1_99.png", b"added").unwrap();
+    assert_eq!(action, MPKPatchAction::Added);
+
+    // Names that don't carry the hash can't be matched
+    let err = patcher
+        .replace_file("res/ui/icon.tex", b"named")
+        .unwrap_err();
+    match mpk_error(&err) {
+        MPKError::InvalidEntryName(name) => assert_eq!(name, "res/ui/icon.tex"),
+        other => panic!("{:?}", other),
+    }
+    patcher.finish().unwrap();
+
+    let reader = MPKFileReader::new(&path).unwrap();
+    assert_eq!(reader.open_entry(0).unwrap().into_inner(), b"patched");
+    assert_eq!(reader.open_entry(1).unwrap().into_inner(), b"added");
+}
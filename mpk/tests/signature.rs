@@ -0,0 +1,119 @@
+use messiah_mpk::{
+    FileSignature, PycSignature, RepositorySignature, SignatureRegistry, TextureSignature,
+};
+
+const SAMPLE_27: &[u8] = include_bytes!("data/sample_27.pyc");
+const SAMPLE_313: &[u8] = include_bytes!("data/sample_313.pyc");
+
+/// Header of a `.repository` with the given type names and folder paths, the size of
+/// the paths written in the long form if `long` is set
+fn repository(types: &[u8], paths: &[u8], long: bool) -> Vec<u8> {
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend([0; 6]);
+    data.extend((types.len() as u16).to_le_bytes());
+    data.extend(types);
+    if long {
+        data.extend(0xFFFFu16.to_le_bytes());
+        data.extend((paths.len() as u32).to_le_bytes());
+    } else {
+        data.extend((paths.len() as u16).to_le_bytes());
+    }
+    data.extend(paths);
+    // The first file record
+    data.extend([0; 21]);
+    data
+}
+
+#[test]
+fn texture() {
+    assert!(TextureSignature.detect(b"\x02\x02\x02\x01\x00\x00\x00\x00"));
+    assert!(TextureSignature.detect(b"\x02\x02\x02\x01"));
+    assert!(!TextureSignature.detect(b"\x02\x02\x02"));
+    assert!(!TextureSignature.detect(b"\x01\x02\x02\x02\x00\x00\x00\x00"));
+    assert!(!TextureSignature.detect(b""));
+}
+
+#[test]
+fn pyc() {
+    assert!(PycSignature.detect(SAMPLE_27));
+    assert!(PycSignature.detect(SAMPLE_313));
+    assert!(PycSignature.detect(&SAMPLE_313[..4]));
+
+    // Magics between releases, without the line break or cut short
+    assert!(!PycSignature.detect(b"\x10\x27\r\n\x00\x00\x00\x00"));
+    assert!(!PycSignature.detect(&[SAMPLE_313[0], SAMPLE_313[1], b'\n', b'\r']));
+    assert!(!PycSignature.detect(&SAMPLE_313[..3]));
+    assert!(!PycSignature.detect(b""));
+}
+
+#[test]
+fn repository_header() {
+    for long in [false, true] {
+        let data = repository(b"Texture2D;Mesh_v2", b"res/ui;res/scene", long);
+        assert!(RepositorySignature.detect(&data));
+    }
+    assert!(RepositorySignature.detect(&repository(b"Texture", "素材".as_bytes(), false)));
+}
+
+#[test]
+fn not_a_repository() {
+    let rejected = [
+        // No type names or no folder paths
+        repository(b"", b"res/ui", false),
+        repository(b"Texture2D", b"", false),
+        repository(b"Texture2D", b"", true),
+        // Type names that aren't identifiers, paths that aren't UTF-8
+        repository(b"Texture 2D", b"res/ui", false),
+        repository(b"Texture2D", b"res/\xFF", false),
+        // Random data and plain text
+        b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x04\x00Mesh\x00\x00".to_vec(),
+        b"Some text that happens to be long enough".to_vec(),
+    ];
+    for data in &rejected {
+        assert!(!RepositorySignature.detect(data), "{:?}", data);
+    }
+
+    // Lists running past the end of the file
+    let data = repository(b"Texture2D", b"res/ui", false);
+    assert!(!RepositorySignature.detect(&data[..12 + 9 + 2 + 3]));
+    assert!(!RepositorySignature.detect(&data[..16]));
+    assert!(!RepositorySignature.detect(&data[..11]));
+}
+
+#[test]
+fn registry() {
+    let registry = SignatureRegistry::default();
+    let repository = repository(b"Texture2D", b"res/ui", false);
+    let extensions = [
+        registry.extension(b"\x02\x02\x02\x01data"),
+        registry.extension(SAMPLE_27),
+        registry.extension(&repository),
+        registry.extension(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"),
+    ];
+    assert_eq!(
+        extensions,
+        [Some("tex"), Some("pyc"), Some("repository"), Some("png")]
+    );
+    assert_eq!(
+        format!("{:?}", registry),
+        "[\"Texture2D\", \"pyc\", \"repository\"]"
+    );
+
+    // Signatures registered later win, without any left only MIME sniffing is
+    struct Everything;
+    impl FileSignature for Everything {
+        fn name(&self) -> &'static str {
+            "everything"
+        }
+        fn extension(&self) -> &'static str {
+            "all"
+        }
+        fn detect(&self, _buffer: &[u8]) -> bool {
+            true
+        }
+    }
+    let mut registry = SignatureRegistry::default();
+    registry.register(Box::new(Everything));
+    assert_eq!(registry.extension(SAMPLE_27), Some("all"));
+    assert_eq!(SignatureRegistry::empty().extension(SAMPLE_27), None);
+}
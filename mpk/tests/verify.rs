@@ -0,0 +1,32 @@
+use messiah_mpk::{MPKCompression, MPKFileReader, MPKVerifyStatus};
+
+mod common;
+use common::{payload, write_archive};
+
+#[test]
+fn verify_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = payload(20);
+    // A lz4 entry cut short, the way partial downloads leave them
+    let mut truncated = b"ZZZ4".to_vec();
+    truncated.extend(lz4_flex::compress_prepend_size(&data));
+    truncated.truncate(truncated.len() / 2);
+    let path = write_archive(
+        dir.path(),
+        &[
+            ("raw.bin", MPKCompression::None, &data),
+            ("zlib.bin", MPKCompression::MangledZlib, &data),
+            ("lz4.bin", MPKCompression::Lz4, &data),
+            ("truncated.bin", MPKCompression::None, &truncated),
+        ],
+    );
+    let results = MPKFileReader::new(path).unwrap().verify_entries().unwrap();
+    let statuses: Vec<_> = results
+        .iter()
+        .map(|result| (result.container.as_str(), &result.status))
+        .collect();
+    assert_eq!(statuses[0], ("raw", &MPKVerifyStatus::Unchecked));
+    assert_eq!(statuses[1].1, &MPKVerifyStatus::Verified);
+    assert_eq!(statuses[2], ("ZZZ4", &MPKVerifyStatus::Unchecked));
+    assert!(matches!(statuses[3], ("ZZZ4", MPKVerifyStatus::Corrupt(_))));
+}
@@ -1,7 +1,7 @@
 use anyhow::Context;
 use clap::{Parser, Subcommand};
 use messiah_resources::Repository;
-use tracing::{error, info};
+use tracing::info;
 use walkdir::WalkDir;
 
 #[derive(Parser)]
@@ -71,7 +71,7 @@ fn main() -> anyhow::Result<()> {
                 if source.exists() {
                     std::fs::create_dir_all(target.parent().unwrap())?;
                 }
-                if let Err(e) = std::fs::rename(&source, &target)
+                if let Err(_e) = std::fs::rename(&source, &target)
                     .with_context(|| format!("{} -> {}", source.display(), target.display()))
                 {
                     failed_counter += 1;
@@ -2,6 +2,7 @@ use std::io::{BufRead, BufReader, Read};
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
+#[allow(dead_code)]
 #[derive(Default)]
 pub struct File {
     unk1: u16,
@@ -54,6 +55,7 @@ impl File {
     }
 }
 
+#[allow(dead_code)]
 pub struct Repository {
     version: u32,
     files: Vec<File>,
@@ -89,10 +91,12 @@ impl Repository {
         while reader.fill_buf().map(|b| !b.is_empty())? {
             //
             // TODO(alexander): Move this to file and have a from_reader thing or something
-            let mut file: File = Default::default();
-            file.unk1 = reader.read_u16::<LittleEndian>()?;
-            file.unk2 = reader.read_u16::<LittleEndian>()?;
-            file.flag = reader.read_u8()?;
+            let mut file = File {
+                unk1: reader.read_u16::<LittleEndian>()?,
+                unk2: reader.read_u16::<LittleEndian>()?,
+                flag: reader.read_u8()?,
+                ..Default::default()
+            };
             reader.read_exact(&mut file.uuid)?;
 
             let file_name_size = reader.read_u16::<LittleEndian>()?;
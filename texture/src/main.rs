@@ -5,6 +5,7 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use clap::{Parser, Subcommand};
 use tracing::{debug, error, info};
 
+use messiah_codec::CodecRegistry;
 use messiah_texture::EPixelFormat;
 
 #[derive(Subcommand)]
@@ -204,6 +205,7 @@ fn main() -> anyhow::Result<()> {
             // Right now we assume it's smallest to largest
             // DDS requires largest to smallest so we build a reverse buffer
             // Ideally we would store them and sort them all in the correct order
+            let codecs = CodecRegistry::default();
             let mut out_texture_data: Vec<u8> = vec![];
             for _mip_level in 0..num_mip_levels {
                 let mip_size_in_bytes = reader.read_u32::<LittleEndian>()?;
@@ -224,18 +226,27 @@ fn main() -> anyhow::Result<()> {
                         reader.read_exact(&mut buf)?;
                         buf
                     }
-                } else if &magic == b"ZZZ4" {
-                    let uncompressed_size = reader.read_u32::<LittleEndian>()?;
-                    let mut buf = vec![0; mip_size_in_bytes as usize - 24];
-                    reader.read_exact(&mut buf)?;
-
-                    lz4_flex::decompress(&buf, uncompressed_size as usize)?
                 } else {
-                    error!("Unknown texture data format: {:?}", &magic);
-                    vec![]
+                    // The mip size counts the 16 byte mip header read above, the container
+                    // magic already read belongs to the payload
+                    let mut buf = magic.to_vec();
+                    buf.resize(
+                        (mip_size_in_bytes as usize)
+                            .saturating_sub(16)
+                            .max(magic.len()),
+                        0,
+                    );
+                    reader.read_exact(&mut buf[magic.len()..])?;
+
+                    if codecs.detect(&buf).is_some() {
+                        codecs.decode(&buf)?.1
+                    } else {
+                        error!("Unknown texture data format: {:?}", &magic);
+                        vec![]
+                    }
                 };
                 debug!("Mip Size: {}", texture_data.len());
-                out_texture_data.splice(0..0, texture_data.into_iter());
+                out_texture_data.splice(0..0, texture_data);
             }
 
             out_data.clone_from_slice(&out_texture_data);